@@ -0,0 +1,8 @@
+CREATE TABLE sessions (
+    provider TEXT NOT NULL,
+    account TEXT NOT NULL,
+    cookies TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    PRIMARY KEY(provider, account)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE scraping_targets
+    ADD COLUMN last_error TEXT;
+ALTER TABLE scraping_targets
+    ADD COLUMN failures INTEGER NOT NULL DEFAULT 0;
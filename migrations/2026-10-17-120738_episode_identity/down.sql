@@ -0,0 +1,19 @@
+DROP INDEX episodes_episode_id;
+
+CREATE TABLE episodes_rename (
+    provider TEXT NOT NULL,
+    id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    title TEXT,
+    images_count INTEGER NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_update TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(provider, id, seq)
+);
+
+INSERT INTO episodes_rename
+    SELECT provider, id, seq, title, images_count, created_at, last_update FROM episodes;
+
+DROP TABLE episodes;
+ALTER TABLE episodes_rename
+    RENAME TO episodes;
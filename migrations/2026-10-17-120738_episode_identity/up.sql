@@ -0,0 +1,12 @@
+ALTER TABLE episodes
+    ADD COLUMN episode_id TEXT;
+ALTER TABLE episodes
+    ADD COLUMN episode_name TEXT;
+
+CREATE UNIQUE INDEX episodes_episode_id
+    ON episodes(provider, id, episode_id);
+
+-- Naver episodes have always been stored by their own number
+UPDATE episodes
+    SET episode_id = CAST(seq AS TEXT), episode_name = CAST(seq AS TEXT)
+    WHERE provider = 'naver';
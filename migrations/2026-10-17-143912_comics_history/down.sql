@@ -0,0 +1 @@
+DROP TABLE comics_history;
@@ -0,0 +1,11 @@
+CREATE TABLE comics_history (
+    provider TEXT NOT NULL,
+    comic_id TEXT NOT NULL,
+    episode_seq INTEGER NOT NULL,
+    episode_name TEXT,
+    image_seq INTEGER NOT NULL,
+    image BLOB NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    archived_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(provider, comic_id, episode_seq, image_seq, archived_at)
+);
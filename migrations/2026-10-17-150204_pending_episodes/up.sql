@@ -0,0 +1,8 @@
+CREATE TABLE pending_episodes (
+    provider TEXT NOT NULL,
+    comic_id TEXT NOT NULL,
+    episode_id TEXT NOT NULL,
+    title TEXT,
+    freed_at TIMESTAMP NOT NULL,
+    PRIMARY KEY(provider, comic_id, episode_id)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE scraping_targets
+    ADD COLUMN scraping_interval INTEGER;
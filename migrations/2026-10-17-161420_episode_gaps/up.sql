@@ -0,0 +1,7 @@
+CREATE TABLE episode_gaps (
+    provider TEXT NOT NULL,
+    comic_id TEXT NOT NULL,
+    episode_id TEXT NOT NULL,
+    found_at TIMESTAMP NOT NULL,
+    PRIMARY KEY(provider, comic_id, episode_id)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE episodes
+    ADD COLUMN published_on DATE;
+ALTER TABLE episodes
+    ADD COLUMN thumbnail BLOB;
@@ -0,0 +1,39 @@
+CREATE TABLE comics_rename (
+    provider TEXT NOT NULL,
+    comic_id TEXT NOT NULL,
+    episode_seq INTEGER NOT NULL,
+    episode_name TEXT,
+    image_seq INTEGER NOT NULL,
+    image BLOB NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(provider, comic_id, episode_seq, image_seq)
+);
+
+INSERT INTO comics_rename
+    SELECT provider, comic_id, episode_seq, episode_name, image_seq, image, updated_at
+    FROM comics;
+
+DROP TABLE comics;
+ALTER TABLE comics_rename
+    RENAME TO comics;
+
+CREATE TABLE comics_history_rename (
+    provider TEXT NOT NULL,
+    comic_id TEXT NOT NULL,
+    episode_seq INTEGER NOT NULL,
+    episode_name TEXT,
+    image_seq INTEGER NOT NULL,
+    image BLOB NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    archived_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY(provider, comic_id, episode_seq, image_seq, archived_at)
+);
+
+INSERT INTO comics_history_rename
+    SELECT provider, comic_id, episode_seq, episode_name, image_seq, image, updated_at,
+        archived_at
+    FROM comics_history;
+
+DROP TABLE comics_history;
+ALTER TABLE comics_history_rename
+    RENAME TO comics_history;
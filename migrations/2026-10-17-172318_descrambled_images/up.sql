@@ -0,0 +1,4 @@
+ALTER TABLE comics
+    ADD COLUMN descrambled BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE comics_history
+    ADD COLUMN descrambled BOOLEAN NOT NULL DEFAULT 0;
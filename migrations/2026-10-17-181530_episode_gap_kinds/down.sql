@@ -0,0 +1,14 @@
+CREATE TABLE episode_gaps_rename (
+    provider TEXT NOT NULL,
+    comic_id TEXT NOT NULL,
+    episode_id TEXT NOT NULL,
+    found_at TIMESTAMP NOT NULL,
+    PRIMARY KEY(provider, comic_id, episode_id)
+);
+
+INSERT INTO episode_gaps_rename
+    SELECT provider, comic_id, episode_id, found_at FROM episode_gaps;
+
+DROP TABLE episode_gaps;
+ALTER TABLE episode_gaps_rename
+    RENAME TO episode_gaps;
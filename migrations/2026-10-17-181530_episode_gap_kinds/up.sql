@@ -0,0 +1,3 @@
+-- 0: missing from the episode list, e.g. deleted; 1: listed but unavailable, e.g. paid
+ALTER TABLE episode_gaps
+    ADD COLUMN kind INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,253 @@
+use crate::error::Result;
+use crate::provider::Provider;
+use anyhow::anyhow;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use url::Url;
+
+/// Login information for a single provider.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Credential {
+    pub id: String,
+    pub password: String,
+}
+
+/// Credentials keyed by provider name, and optionally cookies imported from a browser.
+///
+/// A credential file is a JSON object such as
+/// `{"lezhin": {"id": "...", "password": "..."}, "naver": {"id": "...", "password": "..."}}`.
+/// The legacy format, ID on the first line and password on the second, is read as
+/// Lezhin credential.
+#[derive(Debug, Default)]
+pub struct Credentials {
+    accounts: HashMap<String, Credential>,
+    /// Browser cookies with the URL each of them is set for
+    cookies: Vec<(cookie_store::Cookie<'static>, Url)>,
+}
+
+impl Credentials {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// Adds cookies from a Netscape cookies.txt file, as exported by browser extensions or curl.
+    /// Providers which have cookies in the file skip password login.
+    pub fn with_cookies_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        self.cookies
+            .extend(parse_cookies_txt(&std::fs::read_to_string(path)?)?);
+        Ok(self)
+    }
+
+    /// Returns the credential configured for given provider, if any.
+    pub fn get(&self, provider: Provider) -> Option<&Credential> {
+        self.accounts.get(provider.name())
+    }
+
+    /// Returns imported browser cookies with the URL each of them is set for.
+    pub(crate) fn cookies(&self) -> &[(cookie_store::Cookie<'static>, Url)] {
+        &self.cookies
+    }
+}
+
+impl std::str::FromStr for Credentials {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.trim_start().starts_with('{') {
+            let parsed: HashMap<String, Credential> = serde_json::from_str(s)?;
+            // Provider names are matched regardless of case, and looked up as `Provider::name`
+            let accounts = parsed
+                .into_iter()
+                .map(|(name, cred)| match name.parse::<Provider>() {
+                    Ok(provider) => Ok((provider.name().to_owned(), cred)),
+                    Err(_) => Err(anyhow!("Unrecognized provider {} in credential file", name)),
+                })
+                .collect::<Result<_>>()?;
+            return Ok(Self {
+                accounts,
+                ..Default::default()
+            });
+        }
+
+        let mut lines = s.lines().map(str::trim);
+        match (lines.next(), lines.next()) {
+            (Some(id), Some(password)) if !id.is_empty() => {
+                let mut accounts = HashMap::new();
+                accounts.insert(
+                    Provider::LEZHIN.name().to_owned(),
+                    Credential {
+                        id: id.to_owned(),
+                        password: password.to_owned(),
+                    },
+                );
+                Ok(Self {
+                    accounts,
+                    ..Default::default()
+                })
+            }
+            _ => Err(anyhow!(
+                "Credential file should be a JSON object keyed by provider, or contain ID and PW lines"
+            )),
+        }
+    }
+}
+
+/// Parses Netscape cookies.txt. Each line consists of 7 tab-separated fields:
+/// domain, subdomain flag, path, secure flag, expiration timestamp, name and value.
+fn parse_cookies_txt(text: &str) -> Result<Vec<(cookie_store::Cookie<'static>, Url)>> {
+    let now = chrono::Utc::now().timestamp();
+    let mut cookies = Vec::new();
+
+    for (line_no, line) in text.lines().enumerate() {
+        // curl marks HttpOnly cookies with a prefix, which would look like a comment otherwise
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != 7 {
+            return Err(anyhow!(
+                "Malformed cookies.txt line {}: expected 7 tab-separated fields",
+                line_no + 1
+            ));
+        }
+        let (domain, subdomains, path, secure, expires, name, value) = (
+            fields[0], fields[1], fields[2], fields[3], fields[4], fields[5], fields[6],
+        );
+
+        // Expiration timestamp 0 denotes a session cookie
+        let expires = expires.parse::<i64>()?;
+        if expires != 0 && expires <= now {
+            log::debug!("Ignoring expired cookie {} for {}", name, domain);
+            continue;
+        }
+
+        let host = domain.trim_start_matches('.');
+        let secure = secure == "TRUE";
+        let url = Url::parse(&format!(
+            "{}://{}{}",
+            if secure { "https" } else { "http" },
+            host,
+            path
+        ))?;
+
+        let mut set_cookie = format!("{}={}; Path={}", name, value, path);
+        if subdomains == "TRUE" {
+            set_cookie += &format!("; Domain={}", host);
+        }
+        if expires != 0 {
+            set_cookie += &format!("; Max-Age={}", expires - now);
+        }
+        if secure {
+            set_cookie += "; Secure";
+        }
+        if http_only {
+            set_cookie += "; HttpOnly";
+        }
+
+        cookies.push((
+            cookie_store::Cookie::parse(set_cookie, &url)?.into_owned(),
+            url,
+        ));
+    }
+
+    Ok(cookies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        url.parse().unwrap()
+    }
+
+    #[test]
+    fn cookies_txt() {
+        let expires = chrono::Utc::now().timestamp() + 3600;
+        let text = format!(
+            "# Netscape HTTP Cookie File\n\
+             \n\
+             .naver.com\tTRUE\t/\tTRUE\t{}\tNID_AUT\tauth\n\
+             #HttpOnly_www.lezhin.com\tFALSE\t/\tFALSE\t0\t_lz_session\tsession\n",
+            expires
+        );
+        let cookies = parse_cookies_txt(&text).unwrap();
+        assert_eq!(cookies.len(), 2);
+
+        let (naver, set_for) = &cookies[0];
+        assert_eq!((naver.name(), naver.value()), ("NID_AUT", "auth"));
+        assert_eq!(set_for, &url("https://naver.com/"));
+        assert_eq!(naver.secure(), Some(true));
+        assert!(!naver.is_expired());
+        // The subdomain flag makes it a domain cookie
+        assert!(naver.matches(&url("https://comic.naver.com/webtoon")));
+        assert!(!naver.matches(&url("http://comic.naver.com/webtoon")));
+
+        let (lezhin, set_for) = &cookies[1];
+        assert_eq!((lezhin.name(), lezhin.value()), ("_lz_session", "session"));
+        assert_eq!(set_for, &url("http://www.lezhin.com/"));
+        assert_eq!(lezhin.http_only(), Some(true));
+        // Expiration 0 is a session cookie
+        assert_eq!(
+            serde_json::to_value(lezhin).unwrap()["expires"],
+            "SessionEnd"
+        );
+        // Without the subdomain flag, it is for the host only
+        assert!(lezhin.matches(&url("http://www.lezhin.com/ko")));
+        assert!(!lezhin.matches(&url("http://cdn.www.lezhin.com/")));
+    }
+
+    #[test]
+    fn cookies_txt_expired() {
+        let text = ".naver.com\tTRUE\t/\tFALSE\t1\tNID_AUT\tstale\n";
+        assert!(parse_cookies_txt(text).unwrap().is_empty());
+    }
+
+    #[test]
+    fn cookies_txt_malformed() {
+        let e =
+            parse_cookies_txt("# comment\n.naver.com\tTRUE\t/\tFALSE\t0\tNID_AUT\n").unwrap_err();
+        assert!(e.to_string().contains("line 2"), "{}", e);
+        assert!(parse_cookies_txt(".naver.com\tTRUE\t/\tFALSE\tnever\tNID_AUT\tx\n").is_err());
+    }
+
+    #[test]
+    fn credentials_json() {
+        let credentials: Credentials =
+            r#"{"lezhin": {"id": "reader", "password": "secret"}, "Naver": {"id": "nid", "password": "pw"}}"#
+                .parse()
+                .unwrap();
+        let lezhin = credentials.get(Provider::LEZHIN).unwrap();
+        assert_eq!(
+            (lezhin.id.as_str(), lezhin.password.as_str()),
+            ("reader", "secret")
+        );
+        assert_eq!(credentials.get(Provider::NAVER).unwrap().id, "nid");
+        assert!(credentials.cookies().is_empty());
+
+        assert!(r#"{"unknown": {"id": "reader", "password": "secret"}}"#
+            .parse::<Credentials>()
+            .is_err());
+        assert!(r#"{"lezhin": {"id": "reader"}}"#.parse::<Credentials>().is_err());
+    }
+
+    #[test]
+    fn credentials_legacy_lines() {
+        let credentials: Credentials = "reader\n secret \n".parse().unwrap();
+        let lezhin = credentials.get(Provider::LEZHIN).unwrap();
+        assert_eq!(
+            (lezhin.id.as_str(), lezhin.password.as_str()),
+            ("reader", "secret")
+        );
+        assert!(credentials.get(Provider::NAVER).is_none());
+
+        assert!("reader".parse::<Credentials>().is_err());
+        assert!("\nsecret\n".parse::<Credentials>().is_err());
+    }
+}
@@ -21,7 +21,7 @@ pub enum Error {
     ParseInt(#[error(source)] ParseIntError),
     #[error(display = "Diesel failure")]
     Diesel(#[error(source)] DieselError),
-    #[error(display = "Reqwest failure")]
+    #[error(display = "Authentication failure")]
     AuthFailure,
     #[error(display = "{}", _0)]
     StaticStr(&'static str),
@@ -31,6 +31,8 @@ pub enum Error {
     UnavailableEpisode,
     #[error(display = "ureq failure")]
     Ureq(#[error(source)] Box<UreqError>),
+    #[error(display = "Provider {} does not support {}", _0, _1)]
+    Unsupported(&'static str, &'static str),
 }
 
 impl From<&'static str> for Error {
@@ -0,0 +1,393 @@
+use crate::error::Result;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Delay before the first retry. Doubled on each subsequent retry.
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound of a single retry delay, including one requested by `Retry-After`.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(120);
+
+/// Settings of [`Client`].
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Maximum number of images downloaded at once.
+    pub concurrency: usize,
+    /// Maximum number of retries of a failed request.
+    pub retries: u32,
+    /// Maximum number of requests per second sent to a single host. Unlimited if not positive.
+    pub requests_per_second: f64,
+    /// Base URL of a stand-in server, such as [`crate::replay::StandIn`], which receives every
+    /// request instead of the provider sites. The original host becomes the first path segment.
+    pub stand_in: Option<String>,
+}
+
+impl ClientOptions {
+    /// Returns the URL a request to `url` is actually sent to.
+    pub fn url(&self, url: &str) -> String {
+        let stand_in = match &self.stand_in {
+            Some(stand_in) => stand_in,
+            None => return url.to_owned(),
+        };
+        let parsed = match url::Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => return url.to_owned(),
+        };
+
+        let mut rewritten = format!(
+            "{}/{}{}",
+            stand_in.trim_end_matches('/'),
+            &parsed[url::Position::BeforeHost..url::Position::BeforePath],
+            parsed.path()
+        );
+        if let Some(query) = parsed.query() {
+            rewritten.push('?');
+            rewritten += query;
+        }
+        rewritten
+    }
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            retries: 3,
+            requests_per_second: 4.0,
+            stand_in: None,
+        }
+    }
+}
+
+/// HTTP client shared by providers.
+///
+/// Requests sent through the client are rate-limited per host, and retried with exponential
+/// backoff on transient failures: connection errors, 429 Too Many Requests and 5xx responses.
+#[derive(Clone)]
+pub struct Client {
+    agent: ureq::Agent,
+    options: ClientOptions,
+    limiter: Arc<RateLimiter>,
+}
+
+// ureq::Error carries the whole response, which trips clippy on every closure calling ureq
+#[allow(clippy::result_large_err)]
+impl Client {
+    pub fn new(agent: ureq::Agent, options: ClientOptions) -> Self {
+        Self {
+            agent,
+            limiter: Arc::new(RateLimiter::new(options.requests_per_second)),
+            options: ClientOptions {
+                concurrency: options.concurrency.max(1),
+                ..options
+            },
+        }
+    }
+
+    /// Returns the underlying agent, to access cookies.
+    pub fn agent(&self) -> &ureq::Agent {
+        &self.agent
+    }
+
+    /// Returns the URL a request to `url` is actually sent to.
+    /// It differs from `url` only if a stand-in server is configured.
+    pub fn url(&self, url: &str) -> String {
+        self.options.url(url)
+    }
+
+    /// Builds a GET request to `url`. Requests should be built with this method rather than
+    /// with [`agent`](Self::agent), so that they can be sent to a stand-in server.
+    pub fn get(&self, url: &str) -> ureq::Request {
+        self.agent.get(&self.url(url))
+    }
+
+    /// Builds a POST request to `url`.
+    pub fn post(&self, url: &str) -> ureq::Request {
+        self.agent.post(&self.url(url))
+    }
+
+    pub fn call(&self, request: ureq::Request) -> Result<ureq::Response> {
+        self.send(request, |req| req.call(), Ok)
+    }
+
+    pub fn send_form(
+        &self,
+        request: ureq::Request,
+        form: &[(&str, &str)],
+    ) -> Result<ureq::Response> {
+        self.send(request, |req| req.send_form(form), Ok)
+    }
+
+    pub fn send_string(&self, request: ureq::Request, data: &str) -> Result<ureq::Response> {
+        self.send(request, |req| req.send_string(data), Ok)
+    }
+
+    /// Sends the request and reads the response body as string.
+    pub fn get_string(&self, request: ureq::Request) -> Result<String> {
+        self.send(request, |req| req.call(), ureq::Response::into_string)
+    }
+
+    /// Sends the request and reads the response body.
+    pub fn get_bytes(&self, request: ureq::Request) -> Result<Vec<u8>> {
+        self.send(
+            request,
+            |req| req.call(),
+            |resp| {
+                let mut buf = Vec::new();
+                resp.into_reader().read_to_end(&mut buf)?;
+                Ok(buf)
+            },
+        )
+    }
+
+    /// Sends the request with `send` and reads the response with `read`, retrying both on
+    /// transient failures.
+    fn send<T>(
+        &self,
+        request: ureq::Request,
+        send: impl Fn(ureq::Request) -> Result<ureq::Response, ureq::Error>,
+        read: impl Fn(ureq::Response) -> std::io::Result<T>,
+    ) -> Result<T> {
+        let host = url::Url::parse(request.url())?
+            .host_str()
+            .unwrap_or_default()
+            .to_owned();
+
+        let mut attempt = 0;
+        loop {
+            self.limiter.wait(&host);
+
+            let delay = match send(request.clone()) {
+                Ok(resp) => match read(resp) {
+                    Ok(value) => return Ok(value),
+                    Err(e) if attempt < self.options.retries => {
+                        log::warn!("Cannot read response of {}: {}", request.url(), e);
+                        backoff(attempt)
+                    }
+                    Err(e) => return Err(e.into()),
+                },
+                Err(ureq::Error::Status(code, resp))
+                    if (code == 429 || code >= 500) && attempt < self.options.retries =>
+                {
+                    log::warn!("{} responded with status {}", request.url(), code);
+                    retry_after(&resp).unwrap_or_else(|| backoff(attempt))
+                }
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+                    ) && attempt < self.options.retries =>
+                {
+                    log::warn!("Request to {} failed: {}", request.url(), e);
+                    backoff(attempt)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            attempt += 1;
+            log::info!(
+                "Retrying {} in {:?} ({}/{})",
+                request.url(),
+                delay,
+                attempt,
+                self.options.retries
+            );
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Applies `fetch` to each of `items`, running at most `concurrency` of them at once.
+    /// Results are in the order of `items`. Remaining items are skipped after the first failure.
+    pub fn map_concurrent<T, R, F>(&self, items: &[T], fetch: F) -> Result<Vec<R>>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&T) -> Result<R> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.options.concurrency.min(items.len()) {
+                scope.spawn(|| {
+                    while !failed.load(Ordering::Relaxed) {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        if idx >= items.len() {
+                            break;
+                        }
+
+                        let res = fetch(&items[idx]);
+                        if res.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        results.lock().unwrap()[idx] = Some(res);
+                    }
+                });
+            }
+        });
+
+        // Items skipped after a failure are left as None; the failure itself is reported
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+}
+
+/// Exponential backoff with full jitter: a random delay up to `BASE_RETRY_DELAY * 2^attempt`.
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_RETRY_DELAY
+        .checked_mul(1 << attempt.min(16))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY);
+    // RandomState is randomly seeded, which is good enough for jitter
+    let random = RandomState::new().build_hasher().finish();
+    ceiling.mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+/// Parses `Retry-After` header, which is either delay seconds or an HTTP date.
+fn retry_after(resp: &ureq::Response) -> Option<Duration> {
+    let value = resp.header("retry-after")?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => (chrono::DateTime::parse_from_rfc2822(value)
+            .ok()?
+            .with_timezone(&chrono::Utc)
+            - chrono::Utc::now())
+        .to_std()
+        .unwrap_or_default(),
+    };
+    Some(delay.min(MAX_RETRY_DELAY))
+}
+
+/// Spaces requests to the same host evenly.
+struct RateLimiter {
+    interval: Duration,
+    /// Earliest instant the next request to each host may be sent
+    next: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    fn new(requests_per_second: f64) -> Self {
+        Self {
+            interval: if requests_per_second > 0.0 {
+                Duration::from_secs_f64(1.0 / requests_per_second)
+            } else {
+                Duration::from_secs(0)
+            },
+            next: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Blocks until a request to `host` is allowed.
+    fn wait(&self, host: &str) {
+        if self.interval == Duration::from_secs(0) {
+            return;
+        }
+
+        // Reserve a slot while holding the lock, then sleep without it
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let slot = next.get(host).map_or(now, |t| (*t).max(now));
+            next.insert(host.to_owned(), slot + self.interval);
+            slot
+        };
+
+        let now = Instant::now();
+        if slot > now {
+            std::thread::sleep(slot - now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(retry_after: &str) -> ureq::Response {
+        format!(
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: {}\r\n\r\n",
+            retry_after
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(retry_after(&response("5")), Some(Duration::from_secs(5)));
+        assert_eq!(retry_after(&response(" 0 ")), Some(Duration::from_secs(0)));
+        assert_eq!(retry_after(&response("86400")), Some(MAX_RETRY_DELAY));
+    }
+
+    #[test]
+    fn retry_after_date() {
+        let at = |offset: chrono::Duration| (chrono::Utc::now() + offset).to_rfc2822();
+
+        let delay = retry_after(&response(&at(chrono::Duration::seconds(30)))).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+        assert_eq!(
+            retry_after(&response(&at(chrono::Duration::days(1)))),
+            Some(MAX_RETRY_DELAY)
+        );
+        // A date in the past allows an immediate retry
+        assert_eq!(
+            retry_after(&response(&at(chrono::Duration::seconds(-30)))),
+            Some(Duration::from_secs(0))
+        );
+    }
+
+    #[test]
+    fn retry_after_malformed() {
+        assert_eq!(retry_after(&response("soon")), None);
+        assert_eq!(retry_after(&response("-1")), None);
+        let resp = "HTTP/1.1 503 Service Unavailable\r\n\r\n"
+            .parse::<ureq::Response>()
+            .unwrap();
+        assert_eq!(retry_after(&resp), None);
+    }
+
+    #[test]
+    fn backoff_bounds() {
+        for attempt in 0..40 {
+            let ceiling = (BASE_RETRY_DELAY * 2u32.pow(attempt.min(16))).min(MAX_RETRY_DELAY);
+            for _ in 0..20 {
+                assert!(backoff(attempt) < ceiling, "attempt {}", attempt);
+            }
+        }
+    }
+
+    #[test]
+    fn rate_limiter_spaces_requests() {
+        let limiter = RateLimiter::new(20.0);
+        let start = Instant::now();
+        for _ in 0..5 {
+            limiter.wait("a.example");
+        }
+        // The first request is sent at once, and each of the rest 50ms after the previous one
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // Other hosts have their own slots
+        let start = Instant::now();
+        limiter.wait("b.example");
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn rate_limiter_unlimited() {
+        let limiter = RateLimiter::new(0.0);
+        let start = Instant::now();
+        for _ in 0..100 {
+            limiter.wait("a.example");
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -1,15 +1,22 @@
+#![allow(non_local_definitions)] // derive macros of diesel 1.x and err-derive
+
 #[macro_use]
 extern crate diesel;
 
+pub mod credential;
 pub mod error;
 pub mod export;
+pub mod http;
 #[cfg(feature = "merge")]
 pub mod merge;
 #[cfg(feature = "migrate")]
 pub mod migrate;
 pub mod models;
 pub mod provider;
+pub mod replay;
 pub mod schema;
 pub mod scraper;
+pub mod session;
+pub mod target;
 pub mod util;
 pub mod web;
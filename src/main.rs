@@ -6,9 +6,17 @@ use diesel::prelude::*;
 use diesel::sqlite::SqliteConnection;
 use diesel_migrations::{embed_migrations, RunMigrationsError};
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 
+use lzn::credential::Credentials;
 use lzn::error::Result;
+use lzn::http::ClientOptions;
+use lzn::models::ScrapingStatus;
+use lzn::provider::Provider;
+use lzn::target;
 use lzn::web;
 
 const DEFAULT_DATABASE_NAME: &str = "lzn.sqlite";
@@ -19,6 +27,7 @@ const DEFAULT_LOG_ENV: &str = "lzn=info,actix_web=info";
 enum Cmd {
     /// Merges jpgs from given directory into single image.
     #[structopt(name = "merge")]
+    #[cfg_attr(not(feature = "merge"), allow(dead_code))]
     MergeImages {
         #[structopt(parse(from_os_str))]
         dir: PathBuf,
@@ -28,6 +37,7 @@ enum Cmd {
 
     /// Merges images vertical in each subdirectories of given directory.
     #[structopt(name = "merge-dirs")]
+    #[cfg_attr(not(feature = "merge"), allow(dead_code))]
     MergeDirs {
         #[structopt(parse(from_os_str))]
         dir: PathBuf,
@@ -37,6 +47,7 @@ enum Cmd {
 
     /// Migrates zip archive into database.
     #[structopt(name = "migrate")]
+    #[cfg_attr(not(feature = "migrate"), allow(dead_code))]
     Migrate {
         /// Zip archive to import.
         #[structopt(parse(from_os_str))]
@@ -66,15 +77,35 @@ enum Cmd {
     },
 
     /// Scrape image contents.
-    /// Target comics should be provided in `scraping_targets` table.
+    /// Target comics are managed with `lzn target`.
     #[structopt(name = "scrape")]
     Scrape {
         /// Database path. If not provided defaults to ~/lzn.sqlite
         #[structopt(parse(from_os_str))]
         db: Option<PathBuf>,
-        /// Credential file path. Its first line should be ID and second line should be PW.
-        #[structopt(short, long, parse(from_os_str))]
-        credential: PathBuf,
+        #[structopt(flatten)]
+        credential: CredentialOpt,
+        /// Scrape only targets which have episodes freed since they were found, e.g. from cron.
+        #[structopt(long)]
+        freed_only: bool,
+        #[structopt(flatten)]
+        http: HttpOpt,
+    },
+
+    /// Keep running and scrape each target on schedule, until terminated.
+    /// Targets are scraped once their interval has passed since the last scraping.
+    #[structopt(name = "watch")]
+    Watch {
+        /// Database path. If not provided defaults to ~/lzn.sqlite
+        #[structopt(parse(from_os_str))]
+        db: Option<PathBuf>,
+        #[structopt(flatten)]
+        credential: CredentialOpt,
+        /// Seconds between scrapings of a target without its own `scraping_interval`.
+        #[structopt(long, default_value = "21600")]
+        interval: u64,
+        #[structopt(flatten)]
+        http: HttpOpt,
     },
 
     /// Scrape titles.
@@ -84,9 +115,46 @@ enum Cmd {
         /// Database path. If not provided defaults to ~/lzn.sqlite
         #[structopt(parse(from_os_str))]
         db: Option<PathBuf>,
-        /// Credential file path. Its first line should be ID and second line should be PW.
-        #[structopt(short, long, parse(from_os_str))]
-        credential: PathBuf,
+        #[structopt(flatten)]
+        credential: CredentialOpt,
+        #[structopt(flatten)]
+        http: HttpOpt,
+    },
+
+    /// Check whether scraping a comic still works, without accessing any database.
+    /// Each request and parsing stage is reported, up to the first image of an episode.
+    #[structopt(name = "probe")]
+    Probe {
+        provider: Provider,
+        comic_id: String,
+        #[structopt(flatten)]
+        credential: CredentialOpt,
+        #[structopt(flatten)]
+        http: HttpOpt,
+    },
+
+    /// Manage scraping targets.
+    #[structopt(name = "target")]
+    Target {
+        /// Database path. If not provided defaults to ~/lzn.sqlite
+        #[structopt(long, parse(from_os_str))]
+        db: Option<PathBuf>,
+        #[structopt(subcommand)]
+        cmd: TargetCmd,
+    },
+
+    /// Run a stand-in server for provider sites, used with `--stand-in` of other subcommands.
+    #[structopt(name = "stand-in")]
+    StandIn {
+        /// Forward requests to provider sites and record the exchanges, instead of replaying them.
+        #[structopt(long)]
+        record: bool,
+        /// Directory of recorded exchanges.
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        /// Address to listen HTTP requests.
+        #[structopt(short, long, default_value = "localhost:8334")]
+        addr: String,
     },
 
     /// Export comics into a single .cbz file.
@@ -101,6 +169,169 @@ enum Cmd {
     },
 }
 
+#[derive(Debug, StructOpt)]
+enum TargetCmd {
+    /// Add a target to be scraped, given by provider and comic ID or by comic URL, e.g.
+    /// `lzn target add naver 20853` or
+    /// `lzn target add https://comic.naver.com/webtoon/list?titleId=20853`.
+    #[structopt(name = "add")]
+    Add {
+        /// Provider name, e.g. lezhin or naver, or URL of the comic.
+        provider_or_url: String,
+        /// Comic ID of the provider. Omitted if a URL is given.
+        id: Option<String>,
+    },
+
+    /// Remove a target. Scraped episodes are kept in the database.
+    #[structopt(name = "remove")]
+    Remove { provider: Provider, id: String },
+
+    /// Resume scraping a disabled or complete target.
+    #[structopt(name = "enable")]
+    Enable { provider: Provider, id: String },
+
+    /// Stop scraping a target temporarily.
+    #[structopt(name = "disable")]
+    Disable { provider: Provider, id: String },
+
+    /// Mark a target as fully scraped, so that it is not scraped anymore.
+    #[structopt(name = "complete")]
+    Complete { provider: Provider, id: String },
+
+    /// Set seconds between scrapings of a target in `lzn watch`.
+    /// The `--interval` of `lzn watch` is used again if omitted.
+    #[structopt(name = "interval")]
+    Interval {
+        provider: Provider,
+        id: String,
+        secs: Option<i32>,
+    },
+
+    /// List targets.
+    #[structopt(name = "list")]
+    List {
+        /// Print targets as a JSON array.
+        #[structopt(long)]
+        json: bool,
+    },
+}
+
+impl TargetCmd {
+    fn process(self, conn: &SqliteConnection) -> Result<()> {
+        match self {
+            TargetCmd::Add {
+                provider_or_url,
+                id,
+            } => {
+                let (provider, id) = match id {
+                    Some(id) => (
+                        provider_or_url
+                            .parse::<Provider>()
+                            .map_err(|e| anyhow!("{} {}", e, provider_or_url))?,
+                        id,
+                    ),
+                    None => Provider::from_comic_url(&provider_or_url)?,
+                };
+                target::add(conn, provider, &id)?;
+                log::info!("Added target {}/{}", provider, id);
+            }
+            TargetCmd::Remove { provider, id } => {
+                target::remove(conn, provider, &id)?;
+                log::info!("Removed target {}/{}", provider, id);
+            }
+            TargetCmd::Enable { provider, id } => {
+                target::set_status(conn, provider, &id, ScrapingStatus::Enabled)?;
+            }
+            TargetCmd::Disable { provider, id } => {
+                target::set_status(conn, provider, &id, ScrapingStatus::Disabled)?;
+            }
+            TargetCmd::Complete { provider, id } => {
+                target::set_status(conn, provider, &id, ScrapingStatus::Complete)?;
+            }
+            TargetCmd::Interval { provider, id, secs } => {
+                target::set_interval(conn, provider, &id, secs)?;
+            }
+            TargetCmd::List { json } => {
+                let targets = target::list(conn)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&targets)?);
+                } else {
+                    for t in targets {
+                        println!(
+                            "{}/{}\t{:?}\t{}\t{}\t{}",
+                            t.provider,
+                            t.id,
+                            t.status,
+                            t.last_scraping,
+                            t.scraping_interval
+                                .map_or_else(|| String::from("-"), |secs| format!("{}s", secs)),
+                            t.last_error.unwrap_or_default()
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// HTTP client settings for subcommands accessing providers.
+#[derive(Debug, StructOpt)]
+struct HttpOpt {
+    /// Maximum number of images downloaded at once.
+    #[structopt(short = "j", long, default_value = "4")]
+    concurrency: usize,
+    /// Maximum number of retries of a failed request.
+    #[structopt(long, default_value = "3")]
+    retries: u32,
+    /// Maximum number of requests per second sent to a single host. 0 disables the limit.
+    #[structopt(long, default_value = "4")]
+    rate_limit: f64,
+    /// Send requests to a stand-in server started by `lzn stand-in`, instead of provider sites.
+    #[structopt(long)]
+    stand_in: Option<String>,
+}
+
+impl From<HttpOpt> for ClientOptions {
+    fn from(opt: HttpOpt) -> Self {
+        Self {
+            concurrency: opt.concurrency,
+            retries: opt.retries,
+            requests_per_second: opt.rate_limit,
+            stand_in: opt.stand_in,
+        }
+    }
+}
+
+/// Login settings for subcommands accessing providers.
+#[derive(Debug, StructOpt)]
+struct CredentialOpt {
+    /// Credential file path. A JSON object keyed by provider name, e.g.
+    /// `{"lezhin": {"id": "...", "password": "..."}}`.
+    /// A file whose first line is ID and second line is PW is read as Lezhin credential.
+    /// Providers without credential are scraped without authentication.
+    #[structopt(short, long, parse(from_os_str))]
+    credential: Option<PathBuf>,
+    /// Netscape cookies.txt file exported from a browser, e.g. for social login accounts.
+    /// Providers which have cookies in this file skip password login.
+    #[structopt(long, parse(from_os_str))]
+    cookies: Option<PathBuf>,
+}
+
+impl CredentialOpt {
+    /// Reads the credential and cookies files given.
+    fn load(self) -> Result<Credentials> {
+        let mut credentials = match self.credential {
+            Some(path) => Credentials::from_file(path)?,
+            None => Credentials::default(),
+        };
+        if let Some(path) = self.cookies {
+            credentials = credentials.with_cookies_file(path)?;
+        }
+        Ok(credentials)
+    }
+}
+
 impl Cmd {
     fn process(self) -> Result<()> {
         match self {
@@ -235,10 +466,44 @@ impl Cmd {
 
                 web::serve(addr, conn);
             }
-            Cmd::Scrape { db, credential } => {
-                let cred = std::fs::read_to_string(credential)?;
-                let cred_split = cred.split('\n').collect::<Vec<_>>();
-                let (id, pw) = (cred_split[0].trim(), cred_split[1].trim());
+            Cmd::Probe {
+                provider,
+                comic_id,
+                credential,
+                http,
+            } => {
+                use lzn::provider::StageKind;
+
+                let credentials = credential.load()?;
+
+                let report = lzn::scraper::probe(provider, &comic_id, &credentials, http.into())?;
+                for stage in &report.stages {
+                    match &stage.outcome {
+                        Ok(summary) => println!("ok     {}: {}", stage.name, summary),
+                        Err(e) => println!("FAILED {}: {:#}", stage.name, e),
+                    }
+                }
+
+                if let Some(stage) = report.failure() {
+                    return Err(anyhow!(
+                        "Probe of {}/{} failed at {}; {}",
+                        provider,
+                        comic_id,
+                        stage.name,
+                        match stage.kind {
+                            StageKind::Request => "the site is unreachable or rejected the request",
+                            StageKind::Parse => "the site has likely changed its markup",
+                        }
+                    ));
+                }
+            }
+            Cmd::Scrape {
+                db,
+                credential,
+                freed_only,
+                http,
+            } => {
+                let credentials = credential.load()?;
 
                 let dbpath = match db {
                     Some(path) => path,
@@ -263,14 +528,80 @@ impl Cmd {
                 check_migrations(&conn)?;
 
                 log::info!("Scraping started");
-                lzn::scraper::start(&conn, id, pw)?;
-                log::info!("Scraping complete");
+                let summary = lzn::scraper::start(&conn, &credentials, http.into(), freed_only)?;
+                log::info!(
+                    "Scraping complete: {} targets succeeded, {} targets failed",
+                    summary.succeeded.len(),
+                    summary.failed.len()
+                );
+                for (target, e) in &summary.failed {
+                    log::error!("Failed target {}: {:#}", target, e);
+                }
+                for (target, episode, freed_at) in &summary.pending {
+                    log::info!(
+                        "Episode {} of {} becomes available at {}",
+                        episode,
+                        target,
+                        freed_at
+                    );
+                }
+                if !summary.failed.is_empty() {
+                    std::process::exit(1);
+                }
+            }
+
+            Cmd::Watch {
+                db,
+                credential,
+                interval,
+                http,
+            } => {
+                let credentials = credential.load()?;
+
+                let dbpath = match db {
+                    Some(path) => path,
+                    None => {
+                        let mut path = dirs::home_dir().ok_or_else(|| {
+                            anyhow!("Unable to get home directory of current user")
+                        })?;
+                        path.push(DEFAULT_DATABASE_NAME);
+                        path
+                    }
+                };
+
+                if log::log_enabled!(log::Level::Info) {
+                    log::info!("Opening SQLite DB at {:?}", dbpath);
+                }
+
+                let conn = SqliteConnection::establish(
+                    dbpath.to_str().expect("Converting PathBuf to &str failed"),
+                )
+                .map_err(|e| anyhow!("Cannot connect database: {:?}", e))?;
+
+                check_migrations(&conn)?;
+
+                // The target being scraped is finished before stopping
+                let stop = Arc::new(AtomicBool::new(false));
+                for signal in &[signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+                    signal_hook::flag::register(*signal, Arc::clone(&stop))?;
+                }
+
+                log::info!("Watching targets");
+                lzn::scraper::watch(
+                    &conn,
+                    &credentials,
+                    http.into(),
+                    Duration::from_secs(interval),
+                    &stop,
+                )?;
             }
 
-            Cmd::ScrapeTitles { db, credential } => {
-                let cred = std::fs::read_to_string(credential)?;
-                let cred_split = cred.split('\n').collect::<Vec<_>>();
-                let (id, pw) = (cred_split[0].trim(), cred_split[1].trim());
+            Cmd::ScrapeTitles {
+                db,
+                credential,
+                http,
+            } => {
+                let credentials = credential.load()?;
 
                 let dbpath = match db {
                     Some(path) => path,
@@ -297,10 +628,41 @@ impl Cmd {
                 log::info!("Fetching titles");
                 log::info!(
                     "Complete: {} titles are updated.",
-                    lzn::scraper::scrap_titles(&conn, id, pw)?
+                    lzn::scraper::scrap_titles(&conn, &credentials, http.into())?
                 );
             }
 
+            Cmd::Target { db, cmd } => {
+                let dbpath = match db {
+                    Some(path) => path,
+                    None => {
+                        let mut path = dirs::home_dir().ok_or_else(|| {
+                            anyhow!("Unable to get home directory of current user")
+                        })?;
+                        path.push(DEFAULT_DATABASE_NAME);
+                        path
+                    }
+                };
+
+                let conn = SqliteConnection::establish(
+                    dbpath.to_str().expect("Converting PathBuf to &str failed"),
+                )
+                .map_err(|e| anyhow!("Cannot connect database: {:?}", e))?;
+
+                check_migrations(&conn)?;
+
+                cmd.process(&conn)?;
+            }
+
+            Cmd::StandIn { record, dir, addr } => {
+                let stand_in = if record {
+                    lzn::replay::StandIn::record(dir, &addr)?
+                } else {
+                    lzn::replay::StandIn::replay(dir, &addr)?
+                };
+                println!("Stand-in server listening on {}", stand_in.base_url());
+                stand_in.wait();
+            }
             Cmd::Export { db, out } => {
                 let dbpath = match db {
                     Some(path) => path,
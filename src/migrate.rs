@@ -47,13 +47,14 @@ pub fn migrate_zip(conn: &SqliteConnection, dir: PathBuf) -> Result<(usize, usiz
         file.read_to_end(&mut picture)?;
 
         let record = ComicRecord {
-            provider: crate::provider::Provider::Lezhin,
+            provider: crate::provider::Provider::LEZHIN,
             comic_id: comic,
             episode_seq,
             episode_name: Some(episode),
             image_seq: picture_seq,
             image: picture,
             updated_at: chrono::Local::now().naive_local(),
+            descrambled: false,
         };
         if let Err(e) = diesel::insert_into(schema::lezhin::table)
             .values(&record)
@@ -1,11 +1,15 @@
 use crate::provider::Provider;
-use crate::schema::{comics, episodes, scraping_targets, titles};
-use chrono::NaiveDateTime;
+use crate::schema::{
+    comics, comics_history, episode_gaps, episodes, pending_episodes, scraping_targets, sessions,
+    titles,
+};
+use chrono::{NaiveDate, NaiveDateTime};
 use diesel::backend::Backend;
 use diesel::deserialize::{self, FromSql};
 use diesel::serialize::{self, Output, ToSql};
 use diesel::sql_types::Integer;
 use diesel::sqlite::Sqlite;
+use serde::Serialize;
 use std::io::Write;
 
 #[derive(Queryable, Insertable, Debug)]
@@ -18,6 +22,23 @@ pub(crate) struct ComicRecord {
     pub(crate) image_seq: i32,
     pub(crate) image: Vec<u8>,
     pub(crate) updated_at: NaiveDateTime,
+    /// Whether the image was restored from shuffled tiles
+    pub(crate) descrambled: bool,
+}
+
+/// Image of an episode replaced by a revised version.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "comics_history"]
+pub(crate) struct ComicHistoryRecord {
+    pub(crate) provider: Provider,
+    pub(crate) comic_id: String,
+    pub(crate) episode_seq: i32,
+    pub(crate) episode_name: Option<String>,
+    pub(crate) image_seq: i32,
+    pub(crate) image: Vec<u8>,
+    pub(crate) updated_at: NaiveDateTime,
+    pub(crate) archived_at: NaiveDateTime,
+    pub(crate) descrambled: bool,
 }
 
 #[derive(Queryable, Insertable, Debug)]
@@ -40,11 +61,69 @@ pub(crate) struct EpisodeRecord {
     pub(crate) images_cnt: i32,
     pub(crate) created_at: NaiveDateTime,
     pub(crate) last_update: NaiveDateTime,
+    /// Identifier of the episode given by the provider, unique within a comic
+    pub(crate) episode_id: Option<String>,
+    /// Short name of the episode given by the provider, such as its number
+    pub(crate) episode_name: Option<String>,
+    /// Date the episode was published, if the provider tells
+    pub(crate) published_on: Option<NaiveDate>,
+    pub(crate) thumbnail: Option<Vec<u8>>,
 }
 
-#[derive(AsExpression, FromSqlRow, PartialEq, Debug, Clone)]
+/// An episode which cannot be downloaded, such as a deleted one.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "episode_gaps"]
+pub(crate) struct EpisodeGap {
+    pub(crate) provider: Provider,
+    pub(crate) comic_id: String,
+    pub(crate) episode_id: String,
+    pub(crate) found_at: NaiveDateTime,
+    pub(crate) kind: GapKind,
+}
+
+/// Why an episode cannot be downloaded.
+#[derive(AsExpression, FromSqlRow, PartialEq, Debug, Clone, Copy)]
 #[sql_type = "Integer"]
-pub(crate) enum ScrapingStatus {
+pub enum GapKind {
+    Missing,     // Not in the episode list, e.g. deleted; the comic is complete without it
+    Unavailable, // Listed but cannot be downloaded, e.g. paid preview
+}
+
+impl ToSql<Integer, Sqlite> for GapKind {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
+        let value = match self {
+            Self::Missing => 0,
+            Self::Unavailable => 1,
+        };
+        <i32 as ToSql<Integer, Sqlite>>::to_sql(&value, out)
+    }
+}
+
+impl FromSql<Integer, Sqlite> for GapKind {
+    fn from_sql(bytes: Option<&<Sqlite as Backend>::RawValue>) -> deserialize::Result<Self> {
+        match <i32 as FromSql<Integer, Sqlite>>::from_sql(bytes)? {
+            0 => Ok(Self::Missing),
+            1 => Ok(Self::Unavailable),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+/// An episode which is not free yet, to be scraped once freed.
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "pending_episodes"]
+pub(crate) struct PendingEpisode {
+    pub(crate) provider: Provider,
+    pub(crate) comic_id: String,
+    pub(crate) episode_id: String,
+    pub(crate) title: Option<String>,
+    pub(crate) freed_at: NaiveDateTime,
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, PartialEq, Debug, Clone)]
+#[sql_type = "Integer"]
+#[serde(rename_all = "lowercase")]
+pub enum ScrapingStatus {
     Enabled,  // Target to be scraped; will not scrape existing episodes
     Disabled, // Target temporarily disabled
     Complete, // Full-scraping complete; no need to scrape again
@@ -72,11 +151,28 @@ impl FromSql<Integer, Sqlite> for ScrapingStatus {
     }
 }
 
-#[derive(Queryable, Insertable, Debug)]
+#[derive(Queryable, Insertable, Serialize, Debug)]
 #[table_name = "scraping_targets"]
-pub(crate) struct ScrapingTarget {
+pub struct ScrapingTarget {
+    pub provider: Provider,
+    pub id: String,
+    pub status: ScrapingStatus,
+    pub last_scraping: NaiveDateTime,
+    /// Cause of the last scraping failure, cleared on success
+    pub last_error: Option<String>,
+    /// Number of consecutive scraping failures
+    pub failures: i32,
+    /// Seconds between scrapings in watch mode. The default interval is used if not set
+    pub scraping_interval: Option<i32>,
+}
+
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "sessions"]
+pub(crate) struct SessionRecord {
     pub(crate) provider: Provider,
-    pub(crate) id: String,
-    pub(crate) status: ScrapingStatus,
-    pub(crate) last_scraping: NaiveDateTime,
+    pub(crate) account: String,
+    /// Newline-separated JSON cookies, as written by `CookieStore::save_json`
+    pub(crate) cookies: String,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) expires_at: NaiveDateTime,
 }
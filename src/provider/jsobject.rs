@@ -0,0 +1,481 @@
+//! Tolerant parser for JavaScript values assigned in inline scripts, such as
+//! `__LZ_PRODUCT__ = { product: {...}, departure: '...', };`.
+//!
+//! Object literals may have unquoted keys, single-quoted strings, trailing commas and comments.
+//! Expressions other than literals, e.g. function calls, are skipped and read as `null`, so
+//! that they do not prevent reading other properties.
+use crate::error::Result;
+use anyhow::anyhow;
+use serde_json::{Map, Number, Value};
+
+/// Parses the value assigned to variable `name` in `script`, if any.
+pub(crate) fn parse_assignment(script: &str, name: &str) -> Option<Result<Value>> {
+    let mut offset = 0;
+    while let Some(found) = script[offset..].find(name) {
+        let start = offset + found;
+        offset = start + name.len();
+
+        // Skip longer identifiers containing the name, and comparisons
+        let preceded = script[..start]
+            .chars()
+            .next_back()
+            .is_some_and(is_identifier_char);
+        let rest = script[offset..].trim_start();
+        if preceded || !rest.starts_with('=') || rest.starts_with("==") {
+            continue;
+        }
+
+        let mut parser = Parser {
+            src: script,
+            pos: script.len() - rest.len() + 1,
+        };
+        return Some(parser.value());
+    }
+    None
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn error(&self, expected: &str) -> anyhow::Error {
+        match self.peek() {
+            Some(c) => anyhow!(
+                "Expected {} at offset {}, found {:?}",
+                expected,
+                self.pos,
+                c
+            ),
+            None => anyhow!(
+                "Expected {} at offset {}, found end of script",
+                expected,
+                self.pos
+            ),
+        }
+    }
+
+    /// Skips whitespace and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            let rest = &self.src[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if let Some(comment) = trimmed.strip_prefix("/*") {
+                self.pos += comment.find("*/").map_or(trimmed.len(), |end| end + 4);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_blank();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some(quote @ '"') | Some(quote @ '\'') => Ok(Value::String(self.string(quote)?)),
+            Some(c) if c == '-' || c == '+' || c == '.' || c.is_ascii_digit() => {
+                let start = self.pos;
+                self.number().or_else(|_| {
+                    self.pos = start;
+                    self.skip_expression()
+                })
+            }
+            Some(c) if is_identifier_char(c) => {
+                let start = self.pos;
+                let word = self.identifier();
+                self.skip_blank();
+                // A bare word not followed by an operator or call
+                if matches!(
+                    self.peek(),
+                    Some(',') | Some('}') | Some(']') | Some(';') | None
+                ) {
+                    match word {
+                        "true" => return Ok(Value::Bool(true)),
+                        "false" => return Ok(Value::Bool(false)),
+                        _ => return Ok(Value::Null),
+                    }
+                }
+                self.pos = start;
+                self.skip_expression()
+            }
+            _ => self.skip_expression(),
+        }
+    }
+
+    /// Reads a property value or an array item. A literal which turns out to be a part of a
+    /// longer expression, e.g. `1 + n`, is skipped as a whole.
+    fn item(&mut self, closing: &[char]) -> Result<Value> {
+        self.skip_blank();
+        let start = self.pos;
+        let value = self.value()?;
+        self.skip_blank();
+        match self.peek() {
+            Some(c) if c == ',' || closing.contains(&c) => Ok(value),
+            _ => {
+                self.pos = start;
+                self.skip_expression()
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Value> {
+        self.bump();
+        let mut map = Map::new();
+        loop {
+            self.skip_blank();
+            let key = match self.peek() {
+                Some('}') => {
+                    self.bump();
+                    return Ok(Value::Object(map));
+                }
+                Some(quote @ '"') | Some(quote @ '\'') => self.string(quote)?,
+                Some(c) if is_identifier_char(c) => self.identifier().to_owned(),
+                _ => return Err(self.error("property key")),
+            };
+
+            self.skip_blank();
+            if self.bump() != Some(':') {
+                return Err(self.error("':' after property key"));
+            }
+            let value = self.item(&['}'])?;
+            map.insert(key, value);
+
+            self.skip_blank();
+            match self.bump() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(map)),
+                _ => return Err(self.error("',' or '}' in object")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.bump();
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                Some(']') => {
+                    self.bump();
+                    return Ok(Value::Array(items));
+                }
+                // Elided item
+                Some(',') => items.push(Value::Null),
+                _ => items.push(self.item(&[']'])?),
+            }
+
+            self.skip_blank();
+            match self.bump() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("',' or ']' in array")),
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(is_identifier_char) {
+            self.bump();
+        }
+        &self.src[start..self.pos]
+    }
+
+    fn string(&mut self, quote: char) -> Result<String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('v') => s.push('\u{b}'),
+                    Some('0') => s.push('\0'),
+                    Some('x') => s.push(self.hex_escape(2)?),
+                    Some('u') => s.push(self.unicode_escape()?),
+                    // Line continuation
+                    Some('\n') => {}
+                    Some(c) => s.push(c),
+                    None => return Err(self.error("escaped character")),
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.error("end of string")),
+            }
+        }
+    }
+
+    fn hex_digits(&mut self, len: usize) -> Result<u32> {
+        let code = self
+            .src
+            .get(self.pos..self.pos + len)
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("hexadecimal escape"))?;
+        self.pos += len;
+        Ok(code)
+    }
+
+    fn hex_escape(&mut self, len: usize) -> Result<char> {
+        let code = self.hex_digits(len)?;
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    /// Reads `\uXXXX` escape, combining surrogate pairs.
+    fn unicode_escape(&mut self) -> Result<char> {
+        let code = self.hex_digits(4)?;
+        if (0xd800..0xdc00).contains(&code) && self.src[self.pos..].starts_with("\\u") {
+            self.pos += 2;
+            let low = self.hex_digits(4)?;
+            if (0xdc00..0xe000).contains(&low) {
+                let code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                return Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+        }
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn number(&mut self) -> Result<Value> {
+        let start = self.pos;
+        if matches!(self.peek(), Some('-') | Some('+')) {
+            self.bump();
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+            || (matches!(self.peek(), Some('-') | Some('+'))
+                && self.src[..self.pos].ends_with(['e', 'E']))
+        {
+            self.bump();
+        }
+        let text = self.src[start..self.pos].trim_start_matches('+');
+
+        let number = if let Ok(n) = text.parse::<i64>() {
+            Number::from(n)
+        } else if let Ok(n) = text.parse::<u64>() {
+            Number::from(n)
+        } else {
+            text.parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .ok_or_else(|| anyhow!("Cannot parse number {}", text))?
+        };
+        Ok(Value::Number(number))
+    }
+
+    /// Skips an expression which is not a literal, up to the end of the enclosing property or
+    /// item.
+    fn skip_expression(&mut self) -> Result<Value> {
+        let start = self.pos;
+        let mut depth = 0usize;
+        loop {
+            match self.peek() {
+                Some(quote @ '"') | Some(quote @ '\'') | Some(quote @ '`') => {
+                    self.string(quote)?;
+                }
+                Some('{') | Some('[') | Some('(') => {
+                    depth += 1;
+                    self.bump();
+                }
+                Some('}') | Some(']') | Some(')') | Some(',') | Some(';') | None if depth == 0 => {
+                    break;
+                }
+                Some('}') | Some(']') | Some(')') => {
+                    depth -= 1;
+                    self.bump();
+                }
+                Some(_) => {
+                    self.bump();
+                }
+                None => return Err(self.error("end of expression")),
+            }
+        }
+
+        if self.pos == start {
+            return Err(self.error("value"));
+        }
+        log::debug!("Skipping expression {}", &self.src[start..self.pos]);
+        Ok(Value::Null)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(script: &str) -> Value {
+        parse_assignment(script, "__LZ_PRODUCT__")
+            .expect("assignment")
+            .unwrap()
+    }
+
+    #[test]
+    fn unquoted_keys() {
+        assert_eq!(
+            parse(r#"__LZ_PRODUCT__ = { product: { id: 1, "alias": 'a', $key_2: null } };"#),
+            json!({ "product": { "id": 1, "alias": "a", "$key_2": null } })
+        );
+    }
+
+    #[test]
+    fn trailing_commas() {
+        assert_eq!(
+            parse("__LZ_PRODUCT__ = { product: { episodes: [1, 2, ], }, };"),
+            json!({ "product": { "episodes": [1, 2] } })
+        );
+        // Elided items are holes, unlike a single trailing comma
+        assert_eq!(parse("__LZ_PRODUCT__ = [1, , 3,];"), json!([1, null, 3]));
+    }
+
+    #[test]
+    fn product_followed_by_other_keys() {
+        assert_eq!(
+            parse("__LZ_PRODUCT__ = { product: { id: 1 }, purchased: [2], arrival: '' };"),
+            json!({ "product": { "id": 1 }, "purchased": [2], "arrival": "" })
+        );
+        assert_eq!(
+            parse("__LZ_PRODUCT__ = { productType: 'comic', product: { id: 1 } };"),
+            json!({ "productType": "comic", "product": { "id": 1 } })
+        );
+    }
+
+    #[test]
+    fn layout() {
+        let expected = json!({ "product": { "id": 1, "episodes": [{ "name": "1" }] } });
+        assert_eq!(
+            parse(r#"__LZ_PRODUCT__={product:{id:1,episodes:[{name:"1"}]}};"#),
+            expected
+        );
+        assert_eq!(
+            parse(
+                "\t__LZ_PRODUCT__\r\n\t=\r\n{\r\n\t\tproduct :\r\n{ id\t: 1,\r\n\
+                 episodes: [\r\n{ name: '1' }\r\n]\r\n}\r\n}\r\n"
+            ),
+            expected
+        );
+        // Without a semicolon, the next statement follows on another line
+        assert_eq!(
+            parse("__LZ_PRODUCT__ = {\n  product: {\n    id: 1,\n    episodes: [{ name: '1' }]\n  }\n}\n__LZ_DATA__ = {}"),
+            expected
+        );
+    }
+
+    #[test]
+    fn comments_and_strings() {
+        assert_eq!(
+            parse(
+                r#"__LZ_PRODUCT__ = {
+                    // A comment with } and ' in it
+                    product: { /* id: 2, */ id: 1 },
+                    title: 'It\'s "quoted" // not a comment',
+                    escaped: "\u00e9\ud83d\ude00\x41\n",
+                };"#
+            ),
+            json!({
+                "product": { "id": 1 },
+                "title": "It's \"quoted\" // not a comment",
+                "escaped": "é😀A\n",
+            })
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            parse("__LZ_PRODUCT__ = [0, -1, 1.5, 1e3, 1600000100000, 18446744073709551615];"),
+            json!([
+                0,
+                -1,
+                1.5,
+                1000.0,
+                1600000100000u64,
+                18446744073709551615u64
+            ])
+        );
+    }
+
+    #[test]
+    fn skipped_expressions() {
+        assert_eq!(
+            parse(
+                r#"__LZ_PRODUCT__ = {
+                    purchased: getPurchased(5000, [1, 2], { a: '}' }),
+                    now: new Date(),
+                    sum: 1 + count,
+                    greeting: 'Hello, ' + name,
+                    template: `a${b}c`,
+                    callback: function (x) { return x; },
+                    arrow: (x) => [x],
+                    variable: someVariable,
+                    flag: true,
+                    product: { id: 1 },
+                };"#
+            ),
+            json!({
+                "purchased": null,
+                "now": null,
+                "sum": null,
+                "greeting": null,
+                "template": null,
+                "callback": null,
+                "arrow": null,
+                "variable": null,
+                "flag": true,
+                "product": { "id": 1 },
+            })
+        );
+    }
+
+    #[test]
+    fn comparisons_are_not_assignments() {
+        assert_eq!(
+            parse(
+                "if (window.__LZ_PRODUCT__ == null && __LZ_PRODUCT__ === undefined) {}
+                 my__LZ_PRODUCT__ = { product: { id: 2 } };
+                 __LZ_PRODUCT__x = { product: { id: 3 } };
+                 window.__LZ_PRODUCT__ = { product: { id: 1 } };"
+            ),
+            json!({ "product": { "id": 1 } })
+        );
+        assert!(parse_assignment("if (__LZ_PRODUCT__ == null) {}", "__LZ_PRODUCT__").is_none());
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(parse_assignment("var a = 1;", "__LZ_PRODUCT__").is_none());
+        for script in &[
+            "__LZ_PRODUCT__ = { product: { id: 1 }",
+            "__LZ_PRODUCT__ = { product { id: 1 } };",
+            "__LZ_PRODUCT__ = { product: 'unterminated };",
+        ] {
+            assert!(
+                parse_assignment(script, "__LZ_PRODUCT__").unwrap().is_err(),
+                "{}",
+                script
+            );
+        }
+    }
+}
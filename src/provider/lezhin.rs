@@ -1,4 +1,6 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::http::Client;
+use crate::models::GapKind;
 use anyhow::anyhow;
 use chrono::{offset::TimeZone, DateTime, Utc};
 use diesel::prelude::*;
@@ -6,15 +8,77 @@ use select::document::Document;
 use select::predicate::{And, Attr, Name, Not};
 use serde::Deserialize;
 use std::collections::HashMap;
-use std::io::Read;
 
-#[allow(dead_code)]
-const MAIN_PAGE_URL: &str = "https://www.lezhin.com/ko";
-const AUTH_URL: &str = "https://www.lezhin.com/ko/login?redirect=/ko";
-const EPISODE_LIST_URL: &str = "https://www.lezhin.com/ko/comic/";
+const MAIN_PAGE_URL: &str = "https://www.lezhin.com";
 const COMIC_API_URL: &str = "https://www.lezhin.com/api/v2/inventory_groups/comic_viewer_k";
 const CDN_BASE_URL: &str = "https://cdn.lezhin.com/v2";
 
+pub(crate) struct Lezhin;
+
+impl super::ComicProvider for Lezhin {
+    fn name(&self) -> &'static str {
+        "lezhin"
+    }
+
+    fn base_url(&self) -> &'static str {
+        MAIN_PAGE_URL
+    }
+
+    fn comic_url_example(&self) -> Option<&'static str> {
+        Some("https://www.lezhin.com/ko/comic/<id>")
+    }
+
+    fn parse_comic_url(&self, url: &url::Url) -> Option<String> {
+        parse_comic_url(url)
+    }
+
+    fn validate_comic_id(&self, comic_id: &str) -> Result<()> {
+        ComicId::parse(comic_id).map(|_| ())
+    }
+
+    /// Logs in on every storefront of the comics, as each storefront has its own login.
+    fn authenticate(
+        &self,
+        client: &Client,
+        id: &str,
+        password: &str,
+        comic_ids: &[&str],
+    ) -> Result<()> {
+        let mut locales = Vec::new();
+        for comic in comic_ids.iter().filter_map(|id| ComicId::parse(id).ok()) {
+            if !locales.contains(&comic.locale) {
+                locales.push(comic.locale);
+            }
+        }
+        if locales.is_empty() {
+            locales.push(Locale::Ko);
+        }
+
+        for locale in locales {
+            log::debug!("Logging in on {} storefront", locale.code());
+            authenticate(client, locale, id, password)?;
+        }
+        Ok(())
+    }
+
+    fn fetch_episodes(
+        &self,
+        client: &Client,
+        comic_id: &str,
+        conn: &SqliteConnection,
+    ) -> Result<super::Publication> {
+        fetch_episodes(client, comic_id, conn)
+    }
+
+    fn fetch_titles(&self, client: &Client, comic_ids: Vec<String>) -> Result<Vec<String>> {
+        fetch_titles(client, comic_ids)
+    }
+
+    fn probe(&self, client: &Client, comic_id: &str, report: &mut super::ProbeReport) {
+        probe(client, comic_id, report);
+    }
+}
+
 /// __LZ_PRODUCT__.product JSON schema
 #[derive(Deserialize)]
 #[allow(dead_code)]
@@ -22,9 +86,29 @@ struct LezhinProduct {
     display: HashMap<String, String>,
     alias: String,
     id: u64,
+    /// Publication state, e.g. `scheduled` or `completed`
+    #[serde(default)]
+    state: Option<String>,
     episodes: Vec<EpisodeMetadata>,
 }
 
+impl LezhinProduct {
+    /// Finished comics have `completed` state, and are labeled as such on their schedule.
+    fn publication(&self) -> super::Publication {
+        let completed = self.state.as_deref() == Some("completed")
+            || self.display.get("schedule").is_some_and(|schedule| {
+                ["완결", "完結", "Completed"]
+                    .iter()
+                    .any(|label| schedule.contains(label))
+            });
+        if completed {
+            super::Publication::Finished
+        } else {
+            super::Publication::Ongoing
+        }
+    }
+}
+
 #[derive(Deserialize)]
 #[allow(dead_code)]
 struct EpisodeMetadata {
@@ -58,8 +142,128 @@ where
     }
 }
 
-pub(crate) fn fetch_authenticity_token(agent: &ureq::Agent) -> Result<String> {
-    let resp = agent.get(AUTH_URL).call()?.into_string()?;
+/// Extracts comic ID from a comic or episode URL of any storefront, e.g.
+/// `https://www.lezhin.com/ko/comic/<id>` or `https://www.lezhin.com/en/comic/<id>/<episode>`.
+fn parse_comic_url(url: &url::Url) -> Option<String> {
+    if !matches!(url.host_str(), Some("www.lezhin.com") | Some("lezhin.com")) {
+        return None;
+    }
+
+    let mut segments = url.path_segments()?;
+    let locale = Locale::from_code(segments.next()?)?;
+    match (segments.next(), segments.next()) {
+        (Some("comic"), Some(alias)) if !alias.is_empty() => {
+            Some(ComicId { locale, alias }.to_string())
+        }
+        _ => None,
+    }
+}
+
+/// Storefronts of Lezhin, each under its own path with its own catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Locale {
+    Ko,
+    En,
+    Ja,
+}
+
+impl Locale {
+    const ALL: &'static [Locale] = &[Locale::Ko, Locale::En, Locale::Ja];
+
+    fn code(self) -> &'static str {
+        match self {
+            Self::Ko => "ko",
+            Self::En => "en",
+            Self::Ja => "ja",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|locale| locale.code() == code)
+    }
+
+    fn accept_language(self) -> &'static str {
+        match self {
+            Self::Ko => "ko-KR,ko;q=0.8,en-US;q=0.5,en;q=0.3",
+            Self::En => "en-US,en;q=0.5",
+            Self::Ja => "ja-JP,ja;q=0.8,en-US;q=0.5,en;q=0.3",
+        }
+    }
+
+    /// Locale tag of the storefront, which selects its inventory on the viewer API.
+    fn tag(self) -> &'static str {
+        match self {
+            Self::Ko => "ko-KR",
+            Self::En => "en-US",
+            Self::Ja => "ja-JP",
+        }
+    }
+
+    fn auth_url(self) -> String {
+        format!(
+            "{}/{code}/login?redirect=/{code}",
+            MAIN_PAGE_URL,
+            code = self.code()
+        )
+    }
+}
+
+/// Comic ID of a Lezhin comic, which is its alias prefixed with the storefront unless it is
+/// Korean, e.g. `<alias>` or `en/<alias>`.
+#[derive(Debug, Clone, Copy)]
+struct ComicId<'a> {
+    locale: Locale,
+    alias: &'a str,
+}
+
+impl<'a> ComicId<'a> {
+    fn parse(comic_id: &'a str) -> Result<Self> {
+        // Korean comics are not prefixed
+        let (locale, alias) = match comic_id.split_once('/') {
+            Some((locale, alias)) => (
+                Locale::from_code(locale).filter(|locale| *locale != Locale::Ko),
+                alias,
+            ),
+            None => (Some(Locale::Ko), comic_id),
+        };
+
+        match locale {
+            Some(locale) if !alias.is_empty() && !alias.contains('/') => Ok(Self { locale, alias }),
+            _ => Err(anyhow!(
+                "Unrecognized Lezhin comic ID {}; expected alias, optionally prefixed with en/ or ja/",
+                comic_id
+            )),
+        }
+    }
+
+    fn url(self) -> String {
+        format!(
+            "{}/{}/comic/{}",
+            MAIN_PAGE_URL,
+            self.locale.code(),
+            self.alias
+        )
+    }
+}
+
+impl std::fmt::Display for ComicId<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.locale {
+            Locale::Ko => f.write_str(self.alias),
+            locale => write!(f, "{}/{}", locale.code(), self.alias),
+        }
+    }
+}
+
+fn fetch_authenticity_token(client: &Client, locale: Locale) -> Result<String> {
+    let resp = client.get_string(
+        client
+            .get(&locale.auth_url())
+            .set("Accept-Language", locale.accept_language()),
+    )?;
 
     log::trace!("Auth page response: \n{}", resp);
 
@@ -67,27 +271,42 @@ pub(crate) fn fetch_authenticity_token(agent: &ureq::Agent) -> Result<String> {
     Ok(doc
         .find(And(Name("input"), Attr("name", "authenticity_token")))
         .next()
-        .expect("Expected a authenticity token field on login form")
+        .ok_or_else(|| anyhow!("Expected a authenticity token field on login form"))?
         .attr("value")
-        .expect("authenticity_token field must have a value")
+        .ok_or_else(|| anyhow!("authenticity_token field must have a value"))?
         .to_owned())
 }
 
-pub(crate) fn authenticate(agent: &ureq::Agent, id: &str, password: &str) -> Result<()> {
-    let auth_token = fetch_authenticity_token(agent)?;
+fn authenticate(client: &Client, locale: Locale, id: &str, password: &str) -> Result<()> {
+    let auth_token = fetch_authenticity_token(client, locale)?;
     log::debug!("authenticity_token: {}", auth_token);
 
     let encoded = url::form_urlencoded::Serializer::new(String::new())
         .extend_pairs(&[
             ("utf8", "✓"),
             ("authenticity_token", &auth_token),
-            ("redirect", "/ko"),
+            ("redirect", &format!("/{}", locale.code())),
             ("username", id),
             ("password", password),
             ("remember_me", "false"),
         ])
         .finish();
-    let res = agent.post(AUTH_URL).send_string(&encoded)?;
+    let res = match client.send_string(
+        client
+            .post(&locale.auth_url())
+            .set("Accept-Language", locale.accept_language()),
+        &encoded,
+    ) {
+        Ok(res) => res,
+        Err(e) => {
+            return match e.downcast_ref::<ureq::Error>() {
+                Some(ureq::Error::Status(401, _)) | Some(ureq::Error::Status(403, _)) => {
+                    Err(Error::AuthFailure.into())
+                }
+                _ => Err(e),
+            }
+        }
+    };
 
     log::debug!(
         "Auth response url: {}",
@@ -99,86 +318,79 @@ pub(crate) fn authenticate(agent: &ureq::Agent, id: &str, password: &str) -> Res
         res.header("set-cookie").unwrap_or("<None>")
     );
 
-    // FIXME: If we authenticate outside Korea, how will the url change?
-    // Maybe we should check not final url but initial redirection request,
-    // but reqwest does not support per-request redirection policy currently.
-    // ref: https://github.com/seanmonstar/reqwest/issues/353
-    // update on 20200222: Moved to ureq. Will discuss later.
-    // update on 20200808: Authentication process was changed. No need to check redirection page URL.
-    // if let Some(u) = res.header("Location") {
-    //     if u == "https://www.lezhin.com/ko" {
-    //         Ok(())
-    //     } else {
-    //         Err("Authentication failure: incorrect response url")?
-    //     }
-    // } else {
-    //     unreachable!()
-    // }
+    // On success the login form redirects to the `redirect` page. On failure it renders
+    // the form again, or redirects back to the login page.
+    // If the agent follows redirections, the final URL tells where we landed instead.
+    let landing = res.header("location").unwrap_or_else(|| res.get_url());
+    if landing.contains("/login") {
+        log::debug!("Authentication failed: landed on {}", landing);
+        return Err(Error::AuthFailure.into());
+    }
 
     Ok(())
 }
 
-fn fetch_product_object(agent: &ureq::Agent, comic_id: &str) -> Result<LezhinProduct> {
-    let doc = {
-        let resp = agent
-            .get(&(String::from(EPISODE_LIST_URL) + comic_id))
-            .call()?;
+fn fetch_product_object(client: &Client, comic: ComicId) -> Result<LezhinProduct> {
+    parse_product_object(&fetch_comic_html(client, comic)?)
+}
+
+fn fetch_comic_html(client: &Client, comic: ComicId) -> Result<String> {
+    client.get_string(
+        client
+            .get(&comic.url())
+            .set("Accept-Language", comic.locale.accept_language()),
+    )
+}
 
-        Document::from(resp.into_string()?.as_ref())
-    };
+/// Parses `product` attribute of `__LZ_PRODUCT__` object, assigned in an inline script of
+/// comic page.
+fn parse_product_object(html: &str) -> Result<LezhinProduct> {
+    let doc = Document::from(html);
 
     // Find script tag without id attribute
-    // TODO: Can we flatten nested scopes?
     for sel in doc.find(And(
         Name("script"),
         And(Not(Attr("id", ())), Not(Attr("src", ()))),
     )) {
-        const LZPRODUCT_START_TEXT: &str = "__LZ_PRODUCT__ = ";
-        const PRODUCT_ATTR_START_TEXT: &str = "product: ";
-        const PRODUCT_ATTR_END_TEXT: &str = ",\n        departure";
-        if let Some(text) = sel
-            .children()
-            .next()
-            .expect("expected script tag to have at least one child")
-            .as_text()
-        {
-            if let (Some(start_offset), Some(end_offset)) =
-                (text.find(LZPRODUCT_START_TEXT), text.find("__LZ_DATA__"))
-            {
-                let text = &text[start_offset + LZPRODUCT_START_TEXT.len()..end_offset];
-                if let (Some(json_start), Some(json_end)) = (
-                    text.find(PRODUCT_ATTR_START_TEXT),
-                    text.find(PRODUCT_ATTR_END_TEXT),
-                ) {
-                    return Ok(serde_json::from_reader(
-                        text[(json_start + PRODUCT_ATTR_START_TEXT.len())..json_end].as_bytes(),
-                    )?);
-                } else {
-                    log::warn!(
-                        "Found __LZ_PRODUCT__ object, but product attribute does not exist!"
-                    );
-                    continue;
-                }
+        let text = match sel.children().next().and_then(|node| node.as_text()) {
+            Some(text) => text,
+            None => continue,
+        };
+        let object = match super::jsobject::parse_assignment(text, "__LZ_PRODUCT__") {
+            Some(object) => {
+                object.map_err(|e| anyhow!("Cannot parse __LZ_PRODUCT__ object: {}", e))?
             }
+            None => continue,
+        };
+
+        let product = match object {
+            serde_json::Value::Object(mut attrs) => attrs.remove("product"),
+            _ => None,
         }
+        .ok_or_else(|| {
+            anyhow!("Found __LZ_PRODUCT__ object, but product attribute does not exist")
+        })?;
+        return serde_json::from_value(product)
+            .map_err(|e| anyhow!("Cannot parse product attribute of __LZ_PRODUCT__: {}", e));
     }
 
-    Err(anyhow!("Cannot find LZ_PRODUCT variable"))
+    Err(anyhow!(
+        "Cannot find LZ_PRODUCT variable in inline scripts of comic page"
+    ))
 }
 
 pub(crate) fn fetch_episodes(
-    agent: &ureq::Agent,
+    client: &Client,
     comic_id_: &str,
     conn: &SqliteConnection,
-) -> Result<()> {
-    use crate::models::{ComicRecord, EpisodeRecord, TitleRecord};
-    use crate::schema::comics::dsl::*;
-    use crate::schema::episodes::dsl::*;
+) -> Result<super::Publication> {
+    use crate::models::TitleRecord;
     use crate::schema::titles::dsl::*;
 
-    let eps = fetch_product_object(agent, comic_id_)?;
+    let comic = ComicId::parse(comic_id_)?;
+    let eps = fetch_product_object(client, comic)?;
     let rec = TitleRecord {
-        provider: super::Provider::Lezhin,
+        provider: super::Provider::LEZHIN,
         id: comic_id_.to_owned(),
         title: Some(eps.display["title"].to_owned()),
     };
@@ -200,115 +412,328 @@ pub(crate) fn fetch_episodes(
         diesel::insert_into(titles).values(&rec).execute(conn)?;
     }
 
-    // API response shows recent episodes first, so it must be reversed order
-    for (episode_idx, ep) in eps
-        .episodes
-        .iter()
-        .rev()
-        .filter(|ep| match ep.display.get("type").map(String::as_ref) {
+    let publication = eps.publication();
+    // Episodes which cannot be placed are recorded as gaps, so that the comic is not complete
+    let mut gaps = Vec::new();
+    let mut placed = Vec::new();
+    for ep in &eps.episodes {
+        match ep.display.get("type").map(String::as_ref) {
             Some("n") => {
                 log::debug!("Skipping notice episode {}", ep.display["title"]);
-                false
+                continue;
             }
-            Some(_) => true,
+            Some(_) => {}
             None => {
                 log::warn!(
                     r#"Expected string for display["type"] in episode {}"#,
                     ep.display["title"]
                 );
-                false
+                gaps.push((ep.id.to_string(), GapKind::Unavailable));
+                continue;
             }
-        })
-        .enumerate()
-    {
-        if !comics
-            .filter(crate::schema::comics::dsl::provider.eq(super::Provider::Lezhin))
-            .filter(comic_id.eq(comic_id_.to_owned()))
-            .filter(episode_seq.eq(episode_idx as i32 + 1))
-            .load::<ComicRecord>(conn)?
-            .is_empty()
-        {
-            log::debug!(
-                "Episode sequence {} (title {}) is already scraped. Skipping.",
-                episode_idx as i32 + 1,
+        }
+        match display_seq(&ep.name) {
+            Some(seq) => placed.push((seq, ep)),
+            None => {
+                log::warn!(
+                    "Skipping episode {} with unrecognized name {}",
+                    ep.display["title"],
+                    ep.name
+                );
+                gaps.push((ep.id.to_string(), GapKind::Unavailable));
+            }
+        }
+    }
+    super::save_episode_gaps(conn, super::Provider::LEZHIN, comic_id_, &gaps)?;
+    let eps = placed;
+
+    adopt_legacy_episodes(conn, comic_id_, &eps)?;
+
+    for (seq, ep) in eps.iter().rev() {
+        let ep_id = ep.id.to_string();
+        match super::find_episode(conn, super::Provider::LEZHIN, comic_id_, &ep_id)? {
+            Some(saved)
+                if saved.episode_seq == *seq
+                    && saved.last_update
+                        >= ep.updated_at.with_timezone(&chrono::Local).naive_local() =>
+            {
+                log::debug!(
+                    "Episode {} (title {}) is already scraped. Skipping.",
+                    ep.name,
+                    ep.display["title"]
+                );
+                continue;
+            }
+            Some(saved) if saved.episode_seq != *seq => log::info!(
+                "Episode {} (title {}) was renamed, moving it from sequence {} to {}",
+                ep.name,
+                ep.display["title"],
+                saved.episode_seq,
+                seq
+            ),
+            Some(_) => log::info!(
+                "Episode {} (title {}) was revised since last scraping",
+                ep.name,
                 ep.display["title"]
-            );
-            continue;
+            ),
+            None => {}
         }
 
-        if ep.freed_at.unwrap_or_else(chrono::Utc::now) > chrono::Utc::now() {
-            log::info!("Skipping unavailble episode: {}", ep.display["title"]);
+        let episode = super::Episode {
+            seq: *seq,
+            id: &ep_id,
+            name: &ep.name,
+            title: &ep.display["title"],
+            published_on: None,
+            thumbnail: None,
+            descrambled: false,
+        };
+
+        if let Some(freed_at) = ep.freed_at.filter(|t| *t > chrono::Utc::now()) {
+            let freed_at = freed_at.with_timezone(&chrono::Local).naive_local();
+            log::info!(
+                "Episode {} becomes available at {}",
+                ep.display["title"],
+                freed_at
+            );
+            super::save_pending_episode(
+                conn,
+                super::Provider::LEZHIN,
+                comic_id_,
+                &episode,
+                freed_at,
+            )?;
             continue;
         }
 
         log::info!("Fetching episode: {}", ep.display["title"]);
-        let images = fetch_episode(agent, comic_id_, ep)?;
+        let (images, descrambled) = fetch_episode(client, comic, ep)?;
+        let episode = super::Episode {
+            descrambled,
+            ..episode
+        };
 
-        let recs = images
-            .iter()
-            .enumerate()
-            .map(|(idx, img)| {
-                ComicRecord {
-                    provider: super::Provider::Lezhin,
-                    comic_id: comic_id_.to_owned(),
-                    episode_seq: episode_idx as i32 + 1, // 1-based index
-                    episode_name: Some(ep.display["title"].clone()),
-                    image_seq: idx as i32 + 1, // 1-based index
-                    image: img.to_owned(),
-                    updated_at: chrono::Local::now().naive_local(),
-                }
-            })
-            .collect::<Vec<_>>();
-
-        diesel::insert_into(comics)
-            .values(&recs)
-            .execute(conn)
-            .unwrap_or_else(|e| {
-                log::error!("Cannot insert images into database: {}", e);
-                0
-            });
+        super::save_episode(conn, super::Provider::LEZHIN, comic_id_, &episode, &images)?;
+    }
 
-        diesel::insert_into(episodes)
-            .values(&EpisodeRecord {
-                provider: super::Provider::Lezhin,
-                comic_id: comic_id_.to_owned(),
-                episode_seq: episode_idx as i32 + 1,
-                title: Some(ep.display["title"].clone()),
-                images_cnt: recs.len() as i32,
-                created_at: chrono::Local::now().naive_local(),
-                last_update: chrono::Local::now().naive_local(),
-            })
-            .execute(conn)
-            .unwrap_or_else(|e| {
-                log::error!("Cannot insert images into database: {}", e);
-                0
-            });
+    Ok(publication)
+}
+
+/// Returns the position of an episode in display order, derived from its name.
+///
+/// Prologues are placed before numbered episodes and epilogues after them, so that numbered
+/// episodes keep their own number as sequence.
+fn display_seq(name: &str) -> Option<i32> {
+    const PROLOGUE_SEQ_BASE: i32 = -1000;
+    const EPILOGUE_SEQ_BASE: i32 = 1_000_000;
+
+    if let Some(num) = name.strip_prefix('p') {
+        num.parse::<i32>().ok().map(|num| PROLOGUE_SEQ_BASE + num)
+    } else if let Some(num) = name.strip_prefix('e') {
+        num.parse::<i32>().ok().map(|num| EPILOGUE_SEQ_BASE + num)
+    } else {
+        name.parse().ok()
     }
+}
 
-    Ok(())
+/// Assigns provider identifiers to episodes scraped before they were stored, matching them by
+/// title. Their sequence numbers, which were positions in the episode list at that time, are
+/// replaced with ones derived from episode names.
+///
+/// Episodes which cannot be matched, e.g. removed from Lezhin since, are moved to
+/// `comics_history`, as their positions may be taken by derived sequence numbers. So are images
+/// without an episode, left behind by failed insertions of earlier versions.
+fn adopt_legacy_episodes(
+    conn: &SqliteConnection,
+    comic_id_: &str,
+    eps: &[(i32, &EpisodeMetadata)],
+) -> Result<()> {
+    use crate::schema::comics;
+    use crate::schema::episodes::dsl::*;
+
+    // Sequence numbers are moved past this offset first, so that they never collide midway.
+    // Derived sequence numbers are far smaller than the offset, even negative ones.
+    const TEMP_SEQ_OFFSET: i32 = 1 << 30;
+
+    let now = chrono::Local::now().naive_local();
+    let stored = episodes
+        .filter(provider.eq(super::Provider::LEZHIN))
+        .filter(id.eq(comic_id_))
+        .select(seq)
+        .load::<i32>(conn)?;
+    let orphaned = comics::table
+        .filter(comics::provider.eq(super::Provider::LEZHIN))
+        .filter(comics::comic_id.eq(comic_id_))
+        .filter(comics::episode_seq.ne_all(stored))
+        .select(comics::episode_seq)
+        .distinct()
+        .load::<i32>(conn)?;
+    if !orphaned.is_empty() {
+        conn.transaction::<_, anyhow::Error, _>(|| {
+            for orphan in orphaned {
+                let archived =
+                    super::archive_images(conn, super::Provider::LEZHIN, comic_id_, orphan, now)?;
+                log::warn!(
+                    "Archived {} images of {} without episode at sequence {}",
+                    archived,
+                    comic_id_,
+                    orphan
+                );
+            }
+            Ok(())
+        })?;
+    }
+
+    let legacy = episodes
+        .filter(provider.eq(super::Provider::LEZHIN))
+        .filter(id.eq(comic_id_))
+        .filter(episode_id.is_null())
+        .select((seq, title))
+        .load::<(i32, Option<String>)>(conn)?;
+    if legacy.is_empty() {
+        return Ok(());
+    }
+    let identified = episodes
+        .filter(provider.eq(super::Provider::LEZHIN))
+        .filter(id.eq(comic_id_))
+        .filter(episode_id.is_not_null())
+        .select(seq)
+        .load::<i32>(conn)?;
+
+    conn.transaction::<_, anyhow::Error, _>(|| {
+        let mut adopted = Vec::new();
+        for (old_seq, old_title) in legacy {
+            let ep = eps.iter().find(|(new_seq, ep)| {
+                old_title.as_ref() == ep.display.get("title")
+                    && !adopted.contains(new_seq)
+                    && !identified.contains(new_seq)
+            });
+            let (new_seq, ep) = match ep {
+                Some(ep) => ep,
+                None => {
+                    log::warn!(
+                        "Cannot find episode {} of {} on Lezhin, archiving its images",
+                        old_title.unwrap_or_default(),
+                        comic_id_
+                    );
+                    super::archive_images(conn, super::Provider::LEZHIN, comic_id_, old_seq, now)?;
+                    diesel::delete(
+                        episodes
+                            .filter(provider.eq(super::Provider::LEZHIN))
+                            .filter(id.eq(comic_id_))
+                            .filter(seq.eq(old_seq)),
+                    )
+                    .execute(conn)?;
+                    continue;
+                }
+            };
+            log::debug!(
+                "Episode sequence {} is now {} ({})",
+                old_seq,
+                new_seq,
+                ep.name
+            );
+
+            diesel::update(
+                comics::table
+                    .filter(comics::provider.eq(super::Provider::LEZHIN))
+                    .filter(comics::comic_id.eq(comic_id_))
+                    .filter(comics::episode_seq.eq(old_seq)),
+            )
+            .set(comics::episode_seq.eq(new_seq + TEMP_SEQ_OFFSET))
+            .execute(conn)?;
+            diesel::update(
+                episodes
+                    .filter(provider.eq(super::Provider::LEZHIN))
+                    .filter(id.eq(comic_id_))
+                    .filter(seq.eq(old_seq)),
+            )
+            .set((
+                seq.eq(new_seq + TEMP_SEQ_OFFSET),
+                episode_id.eq(ep.id.to_string()),
+                episode_name.eq(&ep.name),
+            ))
+            .execute(conn)?;
+            adopted.push(*new_seq);
+        }
+
+        diesel::update(
+            comics::table
+                .filter(comics::provider.eq(super::Provider::LEZHIN))
+                .filter(comics::comic_id.eq(comic_id_))
+                .filter(comics::episode_seq.ge(TEMP_SEQ_OFFSET / 2)),
+        )
+        .set(comics::episode_seq.eq(comics::episode_seq - TEMP_SEQ_OFFSET))
+        .execute(conn)?;
+        diesel::update(
+            episodes
+                .filter(provider.eq(super::Provider::LEZHIN))
+                .filter(id.eq(comic_id_))
+                .filter(seq.ge(TEMP_SEQ_OFFSET / 2)),
+        )
+        .set(seq.eq(seq - TEMP_SEQ_OFFSET))
+        .execute(conn)?;
+
+        Ok(())
+    })
 }
 
+/// Downloads images of an episode, and reports whether they were descrambled.
 fn fetch_episode(
-    agent: &ureq::Agent,
-    comic_id: &str,
+    client: &Client,
+    comic: ComicId,
+    episode: &EpisodeMetadata,
+) -> Result<(Vec<Vec<u8>>, bool)> {
+    let info = fetch_episode_info(client, comic, episode)?;
+    let urls = parse_image_urls(&info)?;
+
+    let images = client.map_concurrent(&urls, |url| client.get_bytes(client.get(url)))?;
+    if !is_shuffled(&info) {
+        return Ok((images, false));
+    }
+
+    log::debug!("Descrambling images of episode {}", episode.name);
+    let images = images
+        .iter()
+        .map(|image| super::tiles::descramble(image, episode.id))
+        .collect::<Result<Vec<_>>>()
+        .map_err(|e| {
+            anyhow!(
+                "Cannot descramble images of episode {}: {}",
+                episode.name,
+                e
+            )
+        })?;
+    Ok((images, true))
+}
+
+/// Fetches viewer data of an episode. Storefronts share the viewer API, which serves the
+/// inventory of the storefront given by its locale tag.
+fn fetch_episode_info(
+    client: &Client,
+    comic: ComicId,
     episode: &EpisodeMetadata,
-) -> Result<Vec<Vec<u8>>> {
-    let resp = agent
-        .get(COMIC_API_URL)
-        .set("Accept", "application/json, text/javascript, */*; q=0.01")
-        .set("Accept-Language", "ko-KR,ko;q=0.8,en-US;q=0.5,en;q=0.3")
-        .query("platform", "web")
-        .query("store", "web")
-        .query("alias", comic_id)
-        .query("name", episode.name.as_ref())
-        .query("preload", "true")
-        .query("type", "comic_episode")
-        .call()?;
-
-    let json: serde_json::Value = resp
-        .into_json()
-        .map_err(|_| anyhow!("Non-OK result for comic_view_k API request"))?;
+) -> Result<serde_json::Value> {
+    let resp = client.call(
+        client
+            .get(COMIC_API_URL)
+            .set("Accept", "application/json, text/javascript, */*; q=0.01")
+            .set("Accept-Language", comic.locale.accept_language())
+            .query("platform", "web")
+            .query("store", "web")
+            .query("locale", comic.locale.tag())
+            .query("alias", comic.alias)
+            .query("name", episode.name.as_ref())
+            .query("preload", "true")
+            .query("type", "comic_episode"),
+    )?;
+
+    resp.into_json()
+        .map_err(|_| anyhow!("Non-OK result for comic_view_k API request"))
+}
 
+/// Extracts image URLs from comic_viewer_k API response.
+fn parse_image_urls(json: &serde_json::Value) -> Result<Vec<String>> {
     if json["code"]
         .as_u64()
         .ok_or_else(|| anyhow!("Expected integer code for API response"))?
@@ -326,26 +751,111 @@ fn fetch_episode(
         .ok_or_else(|| anyhow!("Expected list of image items"))?
         .iter()
         .map(|entry| {
-            let url = String::from(CDN_BASE_URL)
+            Ok(String::from(CDN_BASE_URL)
                 + entry["path"]
                     .as_str()
-                    .ok_or_else(|| anyhow!("Expected string path for image item"))?;
-
-            let resp = agent.get(url.as_ref()).call()?;
-            let mut buf = Vec::new();
-            resp.into_reader().read_to_end(&mut buf)?;
-
-            Ok(buf)
+                    .ok_or_else(|| anyhow!("Expected string path for image item"))?)
         })
         .collect::<Result<Vec<_>>>()
 }
 
-pub(crate) fn fetch_titles(agent: &ureq::Agent, comic_ids: Vec<String>) -> Result<Vec<String>> {
+/// Whether comic_viewer_k API response says that tiles of the images are shuffled.
+fn is_shuffled(json: &serde_json::Value) -> bool {
+    json["data"]["extra"]["comic"]["metadata"]["imageShuffle"]
+        .as_bool()
+        .unwrap_or(false)
+}
+
+pub(crate) fn fetch_titles(client: &Client, comic_ids: Vec<String>) -> Result<Vec<String>> {
     comic_ids
         .iter()
         .map(|comic_id| {
             log::debug!("Fetching title for comic ID {}", comic_id);
-            Ok(fetch_product_object(agent, comic_id)?.display["title"].clone())
+            let comic = ComicId::parse(comic_id)?;
+            Ok(fetch_product_object(client, comic)?.display["title"].clone())
         })
         .collect::<Result<Vec<_>>>()
 }
+
+/// Runs probe stages on the oldest available episode, up to its first image. Returns `None`
+/// once a stage fails.
+fn probe(client: &Client, comic_id: &str, report: &mut super::ProbeReport) -> Option<()> {
+    let comic = ComicId::parse(comic_id).ok()?;
+    let html = report.request(
+        "comic page",
+        || fetch_comic_html(client, comic),
+        |html| format!("{} bytes", html.len()),
+    )?;
+    let product = report.parse(
+        "__LZ_PRODUCT__ product attribute",
+        || {
+            let product = parse_product_object(&html)?;
+            if !product.display.contains_key("title") {
+                return Err(anyhow!("Expected title in display attribute of product"));
+            }
+            Ok(product)
+        },
+        |product| {
+            format!(
+                "{}, {:?}, {} episodes",
+                product.display["title"],
+                product.publication(),
+                product.episodes.len()
+            )
+        },
+    )?;
+
+    // Episodes are listed from the latest one
+    let now = Utc::now();
+    let episode = report.parse(
+        "available episode",
+        || {
+            product
+                .episodes
+                .iter()
+                .rev()
+                .filter(|ep| ep.display.get("type").is_some_and(|kind| kind != "n"))
+                .find(|ep| ep.freed_at.is_none_or(|freed_at| freed_at <= now))
+                .ok_or_else(|| anyhow!("No available episode other than notices"))
+        },
+        |ep| {
+            format!(
+                "{} ({})",
+                ep.name,
+                ep.display.get("title").map_or("", String::as_str)
+            )
+        },
+    )?;
+
+    let info = report.request(
+        "comic_viewer_k API",
+        || fetch_episode_info(client, comic, episode),
+        |_| String::from("received JSON"),
+    )?;
+    let urls = report.parse(
+        "comic_viewer_k scrollsInfo",
+        || {
+            let urls = parse_image_urls(&info)?;
+            if urls.is_empty() {
+                return Err(anyhow!("Expected at least one image item"));
+            }
+            Ok(urls)
+        },
+        |urls| format!("{} images", urls.len()),
+    )?;
+
+    let image = report.request(
+        "first image",
+        || client.get_bytes(client.get(&urls[0])),
+        |image| format!("{} bytes", image.len()),
+    )?;
+    if is_shuffled(&info) {
+        report.parse(
+            "descrambled first image",
+            || super::tiles::descramble(&image, episode.id),
+            |image| format!("{} bytes", image.len()),
+        )?;
+    }
+
+    Some(())
+}
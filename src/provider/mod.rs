@@ -1,4 +1,6 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::http::Client;
+use crate::models::GapKind;
 use diesel::backend::Backend;
 use diesel::deserialize::{self, FromSql};
 use diesel::serialize::{self, Output, ToSql};
@@ -6,55 +8,223 @@ use diesel::sql_types::Text;
 use diesel::sqlite::{Sqlite, SqliteConnection};
 use std::io::Write;
 
+mod jsobject;
 mod lezhin;
 mod naver;
+mod tiles;
 
-#[derive(AsExpression, FromSqlRow, Debug, Clone, Copy, PartialEq, Eq)]
-#[sql_type = "Text"]
-pub enum Provider {
-    Lezhin,
-    Naver,
-}
+/// A comic site which can be scraped.
+///
+/// Capabilities a site does not support are reported as [`Error::Unsupported`].
+pub trait ComicProvider: Sync {
+    /// Identifier of this provider, stored in `provider` columns.
+    fn name(&self) -> &'static str;
 
-impl Provider {
-    pub(crate) fn authenticate(&self, agent: &ureq::Agent, id: &str, password: &str) -> Result<()> {
-        match self {
-            Self::Lezhin => lezhin::authenticate(agent, id, password),
-            Self::Naver => Ok(()), // authentication is not required
-        }
+    /// URL of the site. Cookies matching this URL make up a login session.
+    fn base_url(&self) -> &'static str;
+
+    /// Example of a comic URL accepted by [`parse_comic_url`](Self::parse_comic_url), shown
+    /// when a URL is not recognized.
+    fn comic_url_example(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Extracts comic ID from a URL of this site, if it points to a comic.
+    fn parse_comic_url(&self, _url: &url::Url) -> Option<String> {
+        None
     }
 
-    pub(crate) fn fetch_episodes(
+    /// Checks whether a comic ID is well-formed, e.g. when a target is added.
+    fn validate_comic_id(&self, _comic_id: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Names of cookies which hold the login session. Empty if unknown, in which case cookies
+    /// set by the login are taken as such.
+    fn auth_cookies(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Logs in to the site. `comic_ids` are the comics to be scraped with the session, for sites
+    /// whose login differs by comic.
+    fn authenticate(
         &self,
-        agent: &ureq::Agent,
-        comic_id: &str,
-        conn: &SqliteConnection,
+        _client: &Client,
+        _id: &str,
+        _password: &str,
+        _comic_ids: &[&str],
     ) -> Result<()> {
-        match self {
-            Self::Lezhin => lezhin::fetch_episodes(agent, comic_id, conn),
-            Self::Naver => naver::fetch_episodes(agent, comic_id, conn),
-        }
+        Err(Error::Unsupported(self.name(), "authentication").into())
     }
 
-    pub(crate) fn fetch_titles(
+    /// Scrapes episodes of the comic which are not stored yet, and reports whether the comic
+    /// is finished.
+    fn fetch_episodes(
         &self,
-        agent: &ureq::Agent,
-        comic_ids: Vec<String>,
-    ) -> Result<Vec<String>> {
-        match self {
-            Self::Lezhin => lezhin::fetch_titles(agent, comic_ids),
-            Self::Naver => unimplemented!(),
-        }
+        _client: &Client,
+        _comic_id: &str,
+        _conn: &SqliteConnection,
+    ) -> Result<Publication> {
+        Err(Error::Unsupported(self.name(), "fetching episodes").into())
+    }
+
+    fn fetch_titles(&self, _client: &Client, _comic_ids: Vec<String>) -> Result<Vec<String>> {
+        Err(Error::Unsupported(self.name(), "fetching titles").into())
+    }
+
+    /// Runs each request and parsing stage of scraping the comic, without storing anything.
+    /// Stops at the first failing stage.
+    fn probe(&self, _client: &Client, _comic_id: &str, report: &mut ProbeReport) {
+        report.request(
+            "probe",
+            || Err::<(), _>(Error::Unsupported(self.name(), "probing").into()),
+            |_| String::new(),
+        );
+    }
+}
+
+/// Publication state of a comic, as shown by its provider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Publication {
+    Ongoing,
+    Finished,
+}
+
+/// Whether a probe stage talks to the site or reads what it returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageKind {
+    /// Fails on network problems, or when the site rejects the request
+    Request,
+    /// Fails when the site changed its markup or API
+    Parse,
+}
+
+/// A stage run by [`ComicProvider::probe`].
+#[derive(Debug)]
+pub struct ProbeStage {
+    pub kind: StageKind,
+    /// What the stage fetches or looks for
+    pub name: &'static str,
+    /// Summary of what was found, or why the stage failed
+    pub outcome: Result<String>,
+}
+
+/// Stages run by [`ComicProvider::probe`], in order.
+#[derive(Debug, Default)]
+pub struct ProbeReport {
+    pub stages: Vec<ProbeStage>,
+}
+
+impl ProbeReport {
+    /// Returns the failed stage, if any.
+    pub fn failure(&self) -> Option<&ProbeStage> {
+        self.stages.iter().find(|stage| stage.outcome.is_err())
+    }
+
+    /// Runs a stage sending requests to the site.
+    pub(crate) fn request<T>(
+        &mut self,
+        name: &'static str,
+        run: impl FnOnce() -> Result<T>,
+        summary: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        self.run(StageKind::Request, name, run, summary)
+    }
+
+    /// Runs a stage parsing what the site returned.
+    pub(crate) fn parse<T>(
+        &mut self,
+        name: &'static str,
+        run: impl FnOnce() -> Result<T>,
+        summary: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        self.run(StageKind::Parse, name, run, summary)
+    }
+
+    fn run<T>(
+        &mut self,
+        kind: StageKind,
+        name: &'static str,
+        run: impl FnOnce() -> Result<T>,
+        summary: impl FnOnce(&T) -> String,
+    ) -> Option<T> {
+        let (outcome, value) = match run() {
+            Ok(value) => (Ok(summary(&value)), Some(value)),
+            Err(e) => (Err(e), None),
+        };
+        self.stages.push(ProbeStage {
+            kind,
+            name,
+            outcome,
+        });
+        value
+    }
+}
+
+/// Handle to a registered [`ComicProvider`].
+#[derive(AsExpression, FromSqlRow, Clone, Copy)]
+#[sql_type = "Text"]
+pub struct Provider(&'static dyn ComicProvider);
+
+/// Every provider known to lzn. New sites should be registered here.
+const REGISTRY: &[Provider] = &[Provider::LEZHIN, Provider::NAVER];
+
+impl Provider {
+    pub const LEZHIN: Self = Self(&lezhin::Lezhin);
+    pub const NAVER: Self = Self(&naver::Naver);
+
+    /// Returns all registered providers.
+    pub fn registry() -> &'static [Provider] {
+        REGISTRY
+    }
+
+    /// Detects the provider and comic ID of a comic URL.
+    pub fn from_comic_url(url: &str) -> Result<(Provider, String)> {
+        let unrecognized = || {
+            let examples = REGISTRY
+                .iter()
+                .filter_map(|p| p.comic_url_example())
+                .collect::<Vec<_>>();
+            anyhow::anyhow!(
+                "Unrecognized comic URL {}; expected a URL such as {}",
+                url,
+                examples.join(" or ")
+            )
+        };
+
+        let parsed = url::Url::parse(url).map_err(|_| unrecognized())?;
+        REGISTRY
+            .iter()
+            .find_map(|p| p.parse_comic_url(&parsed).map(|id| (*p, id)))
+            .ok_or_else(unrecognized)
+    }
+}
+
+impl std::ops::Deref for Provider {
+    type Target = dyn ComicProvider;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl PartialEq for Provider {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for Provider {}
+
+impl std::hash::Hash for Provider {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name().hash(state)
     }
 }
 
 impl ToSql<Text, Sqlite> for Provider {
     fn to_sql<W: Write>(&self, out: &mut Output<W, Sqlite>) -> serialize::Result {
-        let value = match self {
-            Self::Lezhin => "lezhin",
-            Self::Naver => "naver",
-        };
-        <String as ToSql<Text, Sqlite>>::to_sql(&value.to_string(), out)
+        <str as ToSql<Text, Sqlite>>::to_sql(self.name(), out)
     }
 }
 
@@ -68,19 +238,298 @@ impl FromSql<Text, Sqlite> for Provider {
     }
 }
 
+impl serde::Serialize for Provider {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// Parses a provider name regardless of case, so that names shown by [`Display`] are accepted.
+///
+/// [`Display`]: std::fmt::Display
 impl std::str::FromStr for Provider {
     type Err = &'static str;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "lezhin" => Ok(Self::Lezhin),
-            "naver" => Ok(Self::Naver),
-            _ => Err("Unrecognized enum variant"),
-        }
+        REGISTRY
+            .iter()
+            .find(|p| p.name().eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or("Unrecognized provider")
+    }
+}
+
+impl std::fmt::Debug for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("Provider").field(&self.name()).finish()
     }
 }
 
+/// Capitalized name, e.g. `Lezhin`, as logs and summaries have always shown it.
 impl std::fmt::Display for Provider {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        std::fmt::Debug::fmt(self, f)
+        let mut chars = self.name().chars();
+        if let Some(first) = chars.next() {
+            write!(f, "{}", first.to_uppercase())?;
+        }
+        f.write_str(chars.as_str())
     }
 }
+
+/// An episode as identified by its provider.
+pub(crate) struct Episode<'a> {
+    /// Position of the episode in display order
+    pub(crate) seq: i32,
+    /// Identifier given by the provider, which does not change when other episodes are added
+    pub(crate) id: &'a str,
+    /// Short name given by the provider, such as the episode number
+    pub(crate) name: &'a str,
+    pub(crate) title: &'a str,
+    pub(crate) published_on: Option<chrono::NaiveDate>,
+    pub(crate) thumbnail: Option<&'a [u8]>,
+    /// Whether the images were restored from shuffled tiles
+    pub(crate) descrambled: bool,
+}
+
+/// Returns the stored episode with given provider identifier, if any.
+pub(crate) fn find_episode(
+    conn: &SqliteConnection,
+    provider_: Provider,
+    comic_id: &str,
+    episode_id_: &str,
+) -> Result<Option<crate::models::EpisodeRecord>> {
+    use crate::schema::episodes::dsl::*;
+    use diesel::prelude::*;
+
+    Ok(episodes
+        .filter(provider.eq(provider_))
+        .filter(id.eq(comic_id))
+        .filter(episode_id.eq(episode_id_))
+        .first(conn)
+        .optional()?)
+}
+
+/// Records an episode which becomes available at `freed_at`, to be scraped by a later run.
+pub(crate) fn save_pending_episode(
+    conn: &SqliteConnection,
+    provider: Provider,
+    comic_id: &str,
+    episode: &Episode,
+    freed_at: chrono::NaiveDateTime,
+) -> Result<()> {
+    use crate::models::PendingEpisode;
+    use crate::schema::pending_episodes;
+    use diesel::prelude::*;
+
+    diesel::replace_into(pending_episodes::table)
+        .values(&PendingEpisode {
+            provider,
+            comic_id: comic_id.to_owned(),
+            episode_id: episode.id.to_owned(),
+            title: Some(episode.title.to_owned()),
+            freed_at,
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Records episodes of a comic which cannot be downloaded, identified by the provider, with
+/// why. Previously recorded gaps which are not missing anymore are forgotten.
+pub(crate) fn save_episode_gaps(
+    conn: &SqliteConnection,
+    provider_: Provider,
+    comic_id_: &str,
+    gaps: &[(String, GapKind)],
+) -> Result<()> {
+    use crate::models::EpisodeGap;
+    use crate::schema::episode_gaps::dsl::*;
+    use diesel::prelude::*;
+
+    let now = chrono::Local::now().naive_local();
+    conn.transaction::<_, anyhow::Error, _>(|| {
+        diesel::delete(
+            episode_gaps
+                .filter(provider.eq(provider_))
+                .filter(comic_id.eq(comic_id_))
+                .filter(episode_id.ne_all(gaps.iter().map(|(gap, _)| gap))),
+        )
+        .execute(conn)?;
+
+        // Keep when a gap was found first, but not why, as a paid episode may be deleted later
+        for (gap, kind_) in gaps {
+            diesel::insert_or_ignore_into(episode_gaps)
+                .values(&EpisodeGap {
+                    provider: provider_,
+                    comic_id: comic_id_.to_owned(),
+                    episode_id: gap.clone(),
+                    found_at: now,
+                    kind: *kind_,
+                })
+                .execute(conn)?;
+            diesel::update(episode_gaps.find((provider_, comic_id_, gap)))
+                .set(kind.eq(kind_))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Moves images of the episode at `episode_seq` to `comics_history`, and returns how many there
+/// were.
+pub(crate) fn archive_images(
+    conn: &SqliteConnection,
+    provider: Provider,
+    comic_id: &str,
+    episode_seq: i32,
+    archived_at: chrono::NaiveDateTime,
+) -> Result<usize> {
+    use crate::models::{ComicHistoryRecord, ComicRecord};
+    use crate::schema::{comics, comics_history};
+    use diesel::prelude::*;
+
+    let images = comics::table
+        .filter(comics::provider.eq(provider))
+        .filter(comics::comic_id.eq(comic_id))
+        .filter(comics::episode_seq.eq(episode_seq));
+    let history = images
+        .load::<ComicRecord>(conn)?
+        .into_iter()
+        .map(|rec| ComicHistoryRecord {
+            provider: rec.provider,
+            comic_id: rec.comic_id,
+            episode_seq: rec.episode_seq,
+            episode_name: rec.episode_name,
+            image_seq: rec.image_seq,
+            image: rec.image,
+            updated_at: rec.updated_at,
+            archived_at,
+            descrambled: rec.descrambled,
+        })
+        .collect::<Vec<_>>();
+
+    diesel::insert_into(comics_history::table)
+        .values(&history)
+        .execute(conn)?;
+    diesel::delete(images).execute(conn)?;
+    Ok(history.len())
+}
+
+/// Stores images of an episode and its `episodes` row in a single transaction, so that an
+/// interrupted or failed scraping never leaves a partially saved episode behind.
+///
+/// If the episode is stored already, its images are moved to `comics_history` and replaced.
+/// So are images of another episode stored at the same sequence number.
+pub(crate) fn save_episode(
+    conn: &SqliteConnection,
+    provider: Provider,
+    comic_id: &str,
+    episode: &Episode,
+    images: &[Vec<u8>],
+) -> Result<()> {
+    use crate::models::{ComicRecord, EpisodeRecord};
+    use crate::schema::{comics, episodes, pending_episodes};
+    use diesel::prelude::*;
+
+    let now = chrono::Local::now().naive_local();
+    let recs = images
+        .iter()
+        .enumerate()
+        .map(|(idx, img)| ComicRecord {
+            provider,
+            comic_id: comic_id.to_owned(),
+            episode_seq: episode.seq,
+            episode_name: Some(episode.title.to_owned()),
+            image_seq: idx as i32 + 1, // 1-based index
+            image: img.to_owned(),
+            updated_at: now,
+            descrambled: episode.descrambled,
+        })
+        .collect::<Vec<_>>();
+
+    conn.transaction::<_, anyhow::Error, _>(|| {
+        let created_at = match find_episode(conn, provider, comic_id, episode.id)? {
+            Some(prev) => {
+                let archived = archive_images(conn, provider, comic_id, prev.episode_seq, now)?;
+                log::debug!("Archived {} images of episode {}", archived, episode.name);
+                diesel::delete(
+                    episodes::table
+                        .filter(episodes::provider.eq(provider))
+                        .filter(episodes::id.eq(comic_id))
+                        .filter(episodes::seq.eq(prev.episode_seq)),
+                )
+                .execute(conn)?;
+
+                prev.created_at
+            }
+            None => now,
+        };
+
+        // The sequence number may be held by another episode, e.g. after the provider renamed
+        // episodes, or by images whose episode was never stored
+        let displaced = episodes::table
+            .filter(episodes::provider.eq(provider))
+            .filter(episodes::id.eq(comic_id))
+            .filter(episodes::seq.eq(episode.seq))
+            .first::<EpisodeRecord>(conn)
+            .optional()?;
+        if let Some(displaced) = &displaced {
+            log::warn!(
+                "Episode {} of {} takes sequence {} from episode {}, archiving its images",
+                episode.name,
+                comic_id,
+                episode.seq,
+                displaced.episode_name.as_deref().unwrap_or("(unknown)")
+            );
+            diesel::delete(
+                episodes::table
+                    .filter(episodes::provider.eq(provider))
+                    .filter(episodes::id.eq(comic_id))
+                    .filter(episodes::seq.eq(episode.seq)),
+            )
+            .execute(conn)?;
+        }
+        let archived = archive_images(conn, provider, comic_id, episode.seq, now)?;
+        if archived > 0 && displaced.is_none() {
+            log::warn!(
+                "Archived {} images of {} without episode at sequence {}",
+                archived,
+                comic_id,
+                episode.seq
+            );
+        }
+
+        diesel::insert_into(comics::table)
+            .values(&recs)
+            .execute(conn)?;
+
+        diesel::insert_into(episodes::table)
+            .values(&EpisodeRecord {
+                provider,
+                comic_id: comic_id.to_owned(),
+                episode_seq: episode.seq,
+                title: Some(episode.title.to_owned()),
+                images_cnt: recs.len() as i32,
+                created_at,
+                last_update: now,
+                episode_id: Some(episode.id.to_owned()),
+                episode_name: Some(episode.name.to_owned()),
+                published_on: episode.published_on,
+                thumbnail: episode.thumbnail.map(<[u8]>::to_vec),
+            })
+            .execute(conn)?;
+
+        diesel::delete(pending_episodes::table.find((provider, comic_id, episode.id)))
+            .execute(conn)?;
+
+        Ok(())
+    })
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "Cannot save episode {} of {}: {}",
+            episode.name,
+            comic_id,
+            e
+        )
+    })
+}
@@ -1,78 +1,541 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::http::Client;
+use crate::models::GapKind;
 use anyhow::anyhow;
 use diesel::prelude::*;
 use select::document::Document;
 use select::predicate::{And, Attr, Class, Name};
+use serde::Deserialize;
 
+const LOGIN_URL: &str = "https://nid.naver.com/nidlogin.login";
 const MOBILE_COMIC_BASE_URL: &str = "https://m.comic.naver.com";
-const MOBILE_EPISODE_LIST_URL: &str = "https://m.comic.naver.com/webtoon/list.nhn";
-const COMIC_EPISODE_PAGE_URL: &str = "https://comic.naver.com/webtoon/detail.nhn";
+const COMIC_BASE_URL: &str = "https://comic.naver.com";
+const ARTICLE_INFO_API_URL: &str = "https://comic.naver.com/api/article/list/info";
+const ARTICLE_LIST_API_URL: &str = "https://comic.naver.com/api/article/list";
+const ARTICLE_DETAIL_API_URL: &str = "https://comic.naver.com/api/article/detail";
+/// User agent of episode image requests
+const FAKE_CHROME_74_UA: &str="Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/74.0.3729.169 Safari/537.36";
+
+pub(crate) struct Naver;
+
+impl super::ComicProvider for Naver {
+    fn name(&self) -> &'static str {
+        "naver"
+    }
+
+    fn base_url(&self) -> &'static str {
+        MOBILE_COMIC_BASE_URL
+    }
+
+    fn comic_url_example(&self) -> Option<&'static str> {
+        Some("https://comic.naver.com/webtoon/list?titleId=<id>")
+    }
+
+    fn parse_comic_url(&self, url: &url::Url) -> Option<String> {
+        parse_comic_url(url)
+    }
+
+    fn validate_comic_id(&self, comic_id: &str) -> Result<()> {
+        ComicId::parse(comic_id).map(|_| ())
+    }
+
+    fn auth_cookies(&self) -> &'static [&'static str] {
+        &["NID_AUT", "NID_SES"]
+    }
+
+    fn authenticate(
+        &self,
+        client: &Client,
+        id: &str,
+        password: &str,
+        _comic_ids: &[&str],
+    ) -> Result<()> {
+        authenticate(client, id, password)
+    }
+
+    fn fetch_episodes(
+        &self,
+        client: &Client,
+        comic_id: &str,
+        conn: &SqliteConnection,
+    ) -> Result<super::Publication> {
+        fetch_episodes(client, comic_id, conn)
+    }
+
+    fn fetch_titles(&self, client: &Client, comic_ids: Vec<String>) -> Result<Vec<String>> {
+        fetch_titles(client, comic_ids)
+    }
+
+    fn probe(&self, client: &Client, comic_id: &str, report: &mut super::ProbeReport) {
+        probe(client, comic_id, report);
+    }
+}
+
+/// Extracts comic ID from `titleId` parameter of a list or episode URL of any league, of either
+/// desktop or mobile site.
+fn parse_comic_url(url: &url::Url) -> Option<String> {
+    if !matches!(
+        url.host_str(),
+        Some("comic.naver.com") | Some("m.comic.naver.com")
+    ) {
+        return None;
+    }
+    let league = League::from_path(url.path_segments()?.next()?)?;
+
+    url.query_pairs()
+        .find(|(key, _)| key == "titleId")
+        .map(|(_, id)| id.into_owned())
+        .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+        .map(|title_id| {
+            ComicId {
+                league,
+                title_id: &title_id,
+            }
+            .to_string()
+        })
+}
+
+/// Leagues of Naver series, each under its own path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum League {
+    Webtoon,
+    BestChallenge,
+    Challenge,
+}
+
+impl League {
+    const ALL: &'static [League] = &[League::Webtoon, League::BestChallenge, League::Challenge];
+
+    fn path(self) -> &'static str {
+        match self {
+            Self::Webtoon => "webtoon",
+            Self::BestChallenge => "bestChallenge",
+            Self::Challenge => "challenge",
+        }
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|league| league.path() == path)
+    }
+
+    /// `webtoonLevelCode` of series in the league, as article API tells.
+    fn level_code(self) -> &'static str {
+        match self {
+            Self::Webtoon => "WEBTOON",
+            Self::BestChallenge => "BEST_CHALLENGE",
+            Self::Challenge => "CHALLENGE",
+        }
+    }
+
+    fn from_level_code(code: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|league| league.level_code() == code)
+    }
+}
+
+/// Comic ID of a Naver series, which is its `titleId` prefixed with the league unless it is a
+/// webtoon, e.g. `12345` or `challenge/12345`.
+#[derive(Debug, Clone, Copy)]
+struct ComicId<'a> {
+    league: League,
+    title_id: &'a str,
+}
+
+impl<'a> ComicId<'a> {
+    fn parse(comic_id: &'a str) -> Result<Self> {
+        // Webtoons are not prefixed
+        let (league, title_id) = match comic_id.split_once('/') {
+            Some((league, title_id)) => (
+                League::from_path(league).filter(|league| *league != League::Webtoon),
+                title_id,
+            ),
+            None => (Some(League::Webtoon), comic_id),
+        };
+
+        match league {
+            Some(league) if !title_id.is_empty() && title_id.bytes().all(|b| b.is_ascii_digit()) => {
+                Ok(Self { league, title_id })
+            }
+            _ => Err(anyhow!(
+                "Unrecognized Naver comic ID {}; expected titleId, optionally prefixed with challenge/ or bestChallenge/",
+                comic_id
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for ComicId<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.league {
+            League::Webtoon => f.write_str(self.title_id),
+            league => write!(f, "{}/{}", league.path(), self.title_id),
+        }
+    }
+}
+
+/// Logs in to Naver. Only required for adult-restricted webtoons.
+pub(crate) fn authenticate(client: &Client, id: &str, password: &str) -> Result<()> {
+    let res = client.send_form(
+        client.post(LOGIN_URL),
+        &[
+            ("mode", "form"),
+            ("enctp", "2"),
+            ("locale", "ko_KR"),
+            ("url", MOBILE_COMIC_BASE_URL),
+            ("id", id),
+            ("pw", password),
+        ],
+    )?;
+
+    log::debug!("Auth response code: {}", res.status());
+
+    // Naver sets NID_AUT session cookie only on successful login. The cookie store may still
+    // hold one of a revoked session, so only the response tells.
+    if res.all("set-cookie").iter().any(|cookie| {
+        cookie
+            .strip_prefix("NID_AUT=")
+            .is_some_and(|value| !value.starts_with(';') && !value.is_empty())
+    }) {
+        Ok(())
+    } else {
+        Err(Error::AuthFailure.into())
+    }
+}
+
+/// Response of article list info API, describing a comic.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArticleInfo {
+    title_name: String,
+    finished: bool,
+    /// League of the series, e.g. `WEBTOON` or `CHALLENGE`
+    #[serde(default)]
+    webtoon_level_code: Option<String>,
+}
+
+impl ArticleInfo {
+    fn league(&self) -> Option<League> {
+        self.webtoon_level_code
+            .as_deref()
+            .and_then(League::from_level_code)
+    }
+}
+
+/// Response of article list API, a page of episode list.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArticleListPage {
+    article_list: Vec<Article>,
+    page_info: PageInfo,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Article {
+    /// Episode number
+    no: u32,
+    subtitle: String,
+    thumbnail_url: Option<String>,
+    /// Upload date as `YY.MM.DD`
+    service_date_description: Option<String>,
+    /// Set on paid episodes, such as previews
+    #[serde(default)]
+    charge: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    page: u32,
+    total_pages: u32,
+}
+
+/// Response of article detail API, the viewer data of an episode.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArticleDetail {
+    subtitle: String,
+    image_list: Vec<ArticleImage>,
+}
+
+#[derive(Deserialize)]
+struct ArticleImage {
+    url: String,
+}
+
+impl From<Article> for ListedEpisode {
+    fn from(article: Article) -> Self {
+        Self {
+            no: article.no,
+            title: Some(article.subtitle),
+            published_on: article.service_date_description.and_then(|date| {
+                chrono::NaiveDate::parse_from_str(&date, "%y.%m.%d")
+                    .map_err(|_| log::debug!("Unrecognized upload date {}", date))
+                    .ok()
+            }),
+            thumbnail_url: article.thumbnail_url,
+            available: !article.charge,
+        }
+    }
+}
+
+/// An episode in the episode list of a comic.
+struct ListedEpisode {
+    no: u32,
+    /// Title, if the list shows it
+    title: Option<String>,
+    published_on: Option<chrono::NaiveDate>,
+    thumbnail_url: Option<String>,
+    /// Whether the episode can be downloaded, unlike paid previews
+    available: bool,
+}
+
+/// Episode list of a comic, from every page.
+struct EpisodeList {
+    /// League of the series, if the list tells
+    league: Option<League>,
+    comic_title: String,
+    publication: super::Publication,
+    episodes: Vec<ListedEpisode>,
+}
+
+/// Fetches episode list from article API, or from mobile list pages if the API fails.
+fn fetch_episode_list(client: &Client, comic: ComicId) -> Result<EpisodeList> {
+    let list = fetch_article_list(client, comic).or_else(|e| {
+        log::warn!(
+            "Cannot fetch episode list of {} from article API, reading mobile list pages instead: {:#}",
+            comic,
+            e
+        );
+        fetch_list_pages(client, comic)
+    })?;
+    check_league(comic, list.league)?;
+    Ok(list)
+}
+
+/// Article APIs take `titleId` only, so a series is found whatever league the comic ID names.
+/// Rejects it unless it is in that league.
+fn check_league(comic: ComicId, league: Option<League>) -> Result<()> {
+    match league {
+        Some(league) if league != comic.league => Err(anyhow!(
+            "Naver comic {} is a series of {} league, not {}",
+            comic,
+            league.path(),
+            comic.league.path()
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn fetch_article_info_json(client: &Client, comic: ComicId) -> Result<String> {
+    client.get_string(
+        client
+            .get(ARTICLE_INFO_API_URL)
+            .query("titleId", comic.title_id),
+    )
+}
+
+fn parse_article_info(json: &str) -> Result<ArticleInfo> {
+    serde_json::from_str(json).map_err(|e| anyhow!("Cannot parse article list info: {}", e))
+}
+
+fn fetch_article_list_json(client: &Client, comic: ComicId, page: u32) -> Result<String> {
+    client.get_string(
+        client
+            .get(ARTICLE_LIST_API_URL)
+            .query("titleId", comic.title_id)
+            .query("page", &page.to_string())
+            .query("sort", SortOrder::Ascending.to_str()),
+    )
+}
+
+fn parse_article_list_page(json: &str) -> Result<ArticleListPage> {
+    serde_json::from_str(json).map_err(|e| anyhow!("Cannot parse article list: {}", e))
+}
+
+fn fetch_article_list(client: &Client, comic: ComicId) -> Result<EpisodeList> {
+    let info = parse_article_info(&fetch_article_info_json(client, comic)?)?;
+
+    let mut episodes = Vec::new();
+    for page in 1.. {
+        let list = parse_article_list_page(&fetch_article_list_json(client, comic, page)?)?;
+        let last_page =
+            list.article_list.is_empty() || list.page_info.page >= list.page_info.total_pages;
+        episodes.extend(list.article_list.into_iter().map(ListedEpisode::from));
+        if last_page {
+            break;
+        }
+    }
+
+    Ok(EpisodeList {
+        league: info.league(),
+        comic_title: info.title_name,
+        publication: if info.finished {
+            super::Publication::Finished
+        } else {
+            super::Publication::Ongoing
+        },
+        episodes,
+    })
+}
+
+/// Reads episode list from every mobile list page.
+fn fetch_list_pages(client: &Client, comic: ComicId) -> Result<EpisodeList> {
+    let EpisodeListPage {
+        comic_title,
+        publication,
+        mut episodes,
+        mut unavailable,
+    } = fetch_episode_list_page(client, comic, 1, SortOrder::Ascending)?;
+
+    // Pages past the last one are either empty or repeat the last page
+    for page in 2.. {
+        let last_num = episodes
+            .iter()
+            .map(|ep| ep.0)
+            .chain(unavailable.iter().copied())
+            .max();
+        let list = fetch_episode_list_page(client, comic, page, SortOrder::Ascending)?;
+        let is_new = |num: u32| last_num.is_none_or(|last_num| num > last_num);
+        if !list.episodes.iter().any(|ep| is_new(ep.0))
+            && !list.unavailable.iter().any(|num| is_new(*num))
+        {
+            break;
+        }
+        episodes.extend(list.episodes.into_iter().filter(|ep| is_new(ep.0)));
+        unavailable.extend(list.unavailable.into_iter().filter(|num| is_new(*num)));
+    }
+
+    let listed = |no, available| ListedEpisode {
+        no,
+        // Titles are read from episode pages instead
+        title: None,
+        published_on: None,
+        thumbnail_url: None,
+        available,
+    };
+    let mut episodes = episodes
+        .into_iter()
+        .map(|(no, _, _)| listed(no, true))
+        .chain(unavailable.into_iter().map(|no| listed(no, false)))
+        .collect::<Vec<_>>();
+    episodes.sort_by_key(|ep| ep.no);
+
+    Ok(EpisodeList {
+        // Mobile list pages are under the league path already
+        league: None,
+        comic_title,
+        publication,
+        episodes,
+    })
+}
 
 pub(crate) enum SortOrder {
     Ascending,
-    Descending,
 }
 
 impl SortOrder {
     fn to_str(&self) -> &'static str {
         match self {
             Self::Ascending => "ASC",
-            Self::Descending => "DESC",
         }
     }
 }
 
-/// Parses episode list page into vector of tuples: (number, title, URL).
-#[allow(clippy::type_complexity)]
-pub(crate) fn fetch_episode_list_page(
-    agent: &ureq::Agent,
-    comic_id: &str,
+/// A page of episode list.
+pub(crate) struct EpisodeListPage {
+    pub(crate) comic_title: String,
+    pub(crate) publication: super::Publication,
+    /// Episodes as tuples of (number, title, URL)
+    pub(crate) episodes: Vec<(u32, String, url::Url)>,
+    /// Numbers of listed episodes without a link, such as paid previews
+    pub(crate) unavailable: Vec<u32>,
+}
+
+/// Fetches and parses episode list page.
+fn fetch_episode_list_page(
+    client: &Client,
+    comic: ComicId,
+    page: u32,
+    order: SortOrder,
+) -> Result<EpisodeListPage> {
+    parse_episode_list_page(&fetch_episode_list_html(client, comic, page, order)?)
+}
+
+fn fetch_episode_list_html(
+    client: &Client,
+    comic: ComicId,
     page: u32,
     order: SortOrder,
-) -> Result<(String, Vec<(u32, String, url::Url)>)> {
-    let resp = agent.get(MOBILE_EPISODE_LIST_URL).send_form(&[
-        ("titleId", comic_id),
-        ("sortOrder", order.to_str()),
-        ("page", &page.to_string()),
-    ])?;
+) -> Result<String> {
+    client.get_string(
+        client
+            .get(&format!(
+                "{}/{}/list.nhn",
+                MOBILE_COMIC_BASE_URL,
+                comic.league.path()
+            ))
+            .query("titleId", comic.title_id)
+            .query("sortOrder", order.to_str())
+            .query("page", &page.to_string()),
+    )
+}
 
-    let doc = Document::from(resp.into_string()?.as_ref());
+fn parse_episode_list_page(html: &str) -> Result<EpisodeListPage> {
+    let doc = Document::from(html);
 
-    let episodes = doc
+    let lists = doc
         .find(And(Name("ul"), Class("section_episode_list")))
-        .map(|doc| {
-            doc.find(And(Name("li"), Class("item")))
-                .into_iter()
-                .map(|item| {
-                    Ok((
-                        item.attrs()
+        .collect::<Vec<_>>();
+    if lists.is_empty() {
+        return Err(anyhow!(
+            "Cannot find ul.section_episode_list element in episode list page"
+        ));
+    }
+
+    let items = lists
+        .into_iter()
+        .flat_map(|doc| {
+            doc.find(And(Name("li"), Class("item"))).map(|item| {
+                Ok((
+                    item.attrs()
+                        .collect::<std::collections::HashMap<_, _>>()
+                        .get("data-no")
+                        .ok_or_else(|| {
+                            anyhow!("Cannot parse data-no attribute from episode list item")
+                        })?
+                        .parse::<u32>()?,
+                    item.find(Class("name"))
+                        .next()
+                        .ok_or_else(|| anyhow!("Expected title name in episode item"))?
+                        .text(),
+                    String::from(
+                        *item
+                            .find(And(Name("a"), Class("link")))
+                            .next()
+                            .ok_or_else(|| anyhow!("Cannot find episode page link item"))?
+                            .attrs()
                             .collect::<std::collections::HashMap<_, _>>()
-                            .get("data-no")
+                            .get("href")
                             .ok_or_else(|| {
-                                anyhow!("Cannot parse data-no attribute from episode list item")
-                            })?
-                            .parse::<u32>()?,
-                        item.find(Class("name"))
-                            .next()
-                            .ok_or_else(|| anyhow!("Expected title name in episode item"))?
-                            .text(),
-                        String::from(
-                            *item
-                                .find(And(Name("a"), Class("link")))
-                                .next()
-                                .ok_or_else(|| anyhow!("Cannot find episode page link item"))?
-                                .attrs()
-                                .collect::<std::collections::HashMap<_, _>>()
-                                .get("href")
-                                .ok_or_else(|| {
-                                    anyhow!("Cannot get episode page link data(href) from item")
-                                })?,
-                        ),
-                    ))
-                })
+                                anyhow!("Cannot get episode page link data(href) from item")
+                            })?,
+                    ),
+                ))
+            })
         })
-        .flatten()
-        .collect::<Result<Vec<_>>>()?
+        .collect::<Result<Vec<_>>>()?;
+    let unavailable = items
+        .iter()
+        .filter(|(_, _, url)| url == "#")
+        .map(|(no, _, _)| *no)
+        .collect();
+    let episodes = items
         .into_iter()
         .filter(|(_, _, url)| url != "#")
         .map(|(no, title, url)| {
@@ -89,54 +552,120 @@ pub(crate) fn fetch_episode_list_page(
         .next()
         .ok_or_else(|| anyhow!("Expected comic title metadata in episode list page"))?
         .attr("content")
-        .expect("Found og:title metadata in episode list but there is no content attribute")
+        .ok_or_else(|| anyhow!("Expected content attribute of og:title metadata"))?
         .to_string();
 
-    Ok((comic_title, episodes))
+    // Finished webtoons have a badge next to their title
+    let publication = if doc.find(Class("ico_end")).next().is_some() {
+        super::Publication::Finished
+    } else {
+        super::Publication::Ongoing
+    };
+
+    Ok(EpisodeListPage {
+        comic_title,
+        publication,
+        episodes,
+        unavailable,
+    })
 }
 
-pub(crate) fn fetch_episode(
-    agent: &ureq::Agent,
-    comic_id_: &str,
+fn fetch_episode(
+    client: &Client,
+    comic: ComicId,
     episode_num: u32,
 ) -> Result<(String, Vec<Vec<u8>>)> {
-    use std::io::Read;
-    const FAKE_CHROME_74_UA: &str="Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/74.0.3729.169 Safari/537.36";
-
-    // let url = Url::parse_with_params(
-    //     COMIC_EPISODE_PAGE_URL,
-    //     &[("titleId", comic_id_), ("no", &episode_num.to_string())],
-    // )
-    // .expect("Generated URL must be valid");
-
-    let doc = {
-        let resp = agent
-            .get(COMIC_EPISODE_PAGE_URL)
-            .send_form(&[("titleId", comic_id_), ("no", &episode_num.to_string())])?;
-        Document::from(resp.into_string()?.as_ref())
-    };
+    let (title, image_links) = fetch_episode_viewer(client, comic, episode_num)?;
+
+    let images = client.map_concurrent(&image_links, |link| {
+        log::debug!("image link: {}", link);
+        client.get_bytes(client.get(link).set("User-Agent", FAKE_CHROME_74_UA))
+    })?;
+
+    Ok((title, images))
+}
+
+/// Fetches episode title and image links from article detail API, or from episode page if the
+/// API fails for other reasons than the session.
+fn fetch_episode_viewer(
+    client: &Client,
+    comic: ComicId,
+    episode_num: u32,
+) -> Result<(String, Vec<String>)> {
+    match fetch_article_detail_json(client, comic, episode_num)
+        .and_then(|json| parse_article_detail(&json))
+    {
+        Ok(detail) => Ok((
+            detail.subtitle,
+            detail
+                .image_list
+                .into_iter()
+                .map(|image| image.url)
+                .collect(),
+        )),
+        // The episode page would reject the session likewise
+        Err(e) if is_rejected(&e) => Err(e),
+        Err(e) => {
+            log::warn!(
+                "Cannot fetch episode {} of {} from article API, reading episode page instead: {:#}",
+                episode_num,
+                comic,
+                e
+            );
+            parse_episode_page(&fetch_episode_html(client, comic, episode_num)?)
+        }
+    }
+}
+
+/// Whether the site refused the request for lack of a valid session.
+fn is_rejected(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<ureq::Error>(),
+            Some(ureq::Error::Status(401, _)) | Some(ureq::Error::Status(403, _))
+        )
+    })
+}
+
+fn fetch_article_detail_json(client: &Client, comic: ComicId, episode_num: u32) -> Result<String> {
+    client.get_string(
+        client
+            .get(ARTICLE_DETAIL_API_URL)
+            .query("titleId", comic.title_id)
+            .query("no", &episode_num.to_string()),
+    )
+}
+
+fn parse_article_detail(json: &str) -> Result<ArticleDetail> {
+    serde_json::from_str(json).map_err(|e| anyhow!("Cannot parse article detail: {}", e))
+}
+
+fn fetch_episode_html(client: &Client, comic: ComicId, episode_num: u32) -> Result<String> {
+    client.get_string(
+        client
+            .get(&format!(
+                "{}/{}/detail.nhn",
+                COMIC_BASE_URL,
+                comic.league.path()
+            ))
+            .query("titleId", comic.title_id)
+            .query("no", &episode_num.to_string()),
+    )
+}
+
+/// Parses episode title and image links from episode page.
+fn parse_episode_page(html: &str) -> Result<(String, Vec<String>)> {
+    let doc = Document::from(html);
     let image_links = doc
         .find(Class("wt_viewer"))
         .next()
         .ok_or_else(|| anyhow!("Expected wt_viewer element in episode page"))?
         .find(Name("img"))
-        .into_iter()
         .map(|item| {
             item.attr("src")
+                .map(String::from)
                 .ok_or_else(|| anyhow!("Expected src link in episode img element"))
         })
-        .collect::<Result<Vec<_>, _>>()?;
-
-    let images = image_links
-        .into_iter()
-        .map(|link| {
-            log::debug!("image link: {}", link);
-            let resp = agent
-                .get(link)
-                .set("User-Agent", FAKE_CHROME_74_UA)
-                .call()?;
-            Ok(resp.into_reader().bytes().collect::<Result<Vec<_>, _>>()?)
-        })
         .collect::<Result<Vec<_>>>()?;
 
     let title = doc
@@ -145,31 +674,32 @@ pub(crate) fn fetch_episode(
         .ok_or_else(|| anyhow!("Expected title area element in episode page"))?
         .find(Name("h3"))
         .next()
-        .expect("Expected title")
+        .ok_or_else(|| anyhow!("Expected h3 title in title area element"))?
         .text();
 
-    Ok((title, images))
+    Ok((title, image_links))
 }
 
 pub(crate) fn fetch_episodes(
-    agent: &ureq::Agent,
+    client: &Client,
     comic_id_: &str,
     conn: &SqliteConnection,
-) -> Result<()> {
-    use crate::models::{ComicRecord, EpisodeRecord, TitleRecord};
-    use crate::schema::comics::dsl::*;
-    use crate::schema::episodes::dsl::*;
+) -> Result<super::Publication> {
+    use crate::models::TitleRecord;
     use crate::schema::titles::dsl::*;
 
-    let (comic_title, first_list) =
-        fetch_episode_list_page(agent, comic_id_, 1, SortOrder::Ascending)?;
-    let first_num = first_list[0].0;
-    let last_num = (fetch_episode_list_page(agent, comic_id_, 1, SortOrder::Descending)?.1)[0].0;
+    let comic = ComicId::parse(comic_id_)?;
+    let EpisodeList {
+        comic_title,
+        publication,
+        episodes,
+        ..
+    } = fetch_episode_list(client, comic)?;
 
     log::info!("Title found for current comic: {}", comic_title);
 
     let rec = TitleRecord {
-        provider: super::Provider::Naver,
+        provider: super::Provider::NAVER,
         id: comic_id_.to_owned(),
         title: Some(comic_title),
     };
@@ -191,14 +721,31 @@ pub(crate) fn fetch_episodes(
         diesel::insert_into(titles).values(&rec).execute(conn)?;
     }
 
-    for ep_num in first_num..=last_num {
-        if !comics
-            .filter(crate::schema::comics::dsl::provider.eq(super::Provider::Naver))
-            .filter(comic_id.eq(comic_id_.to_owned()))
-            .filter(episode_seq.eq(ep_num as i32))
-            .load::<ComicRecord>(conn)?
-            .is_empty()
-        {
+    // Episodes are numbered from 1, but deleted ones or paid previews cannot be downloaded
+    let last_num = episodes.iter().map(|ep| ep.no).max();
+    let gaps = (1..=last_num.unwrap_or(0))
+        .filter_map(|num| match episodes.iter().find(|ep| ep.no == num) {
+            Some(ep) if ep.available => None,
+            Some(_) => Some((num.to_string(), GapKind::Unavailable)),
+            None => Some((num.to_string(), GapKind::Missing)),
+        })
+        .collect::<Vec<_>>();
+    if !gaps.is_empty() {
+        log::info!(
+            "Episodes {} of {} cannot be downloaded; recording them as gaps",
+            gaps.iter()
+                .map(|(num, kind)| format!("{} ({:?})", num, kind))
+                .collect::<Vec<_>>()
+                .join(", "),
+            comic_id_
+        );
+    }
+    super::save_episode_gaps(conn, super::Provider::NAVER, comic_id_, &gaps)?;
+
+    for ep in episodes.iter().filter(|ep| ep.available) {
+        let ep_num = ep.no;
+        let ep_id = ep_num.to_string();
+        if super::find_episode(conn, super::Provider::NAVER, comic_id_, &ep_id)?.is_some() {
             log::debug!(
                 "Skipping episode sequence {} because record exists already",
                 ep_num
@@ -206,47 +753,186 @@ pub(crate) fn fetch_episodes(
             continue;
         }
 
-        let (title_, eps) = fetch_episode(agent, comic_id_, ep_num)?;
+        let (page_title, eps) = fetch_episode(client, comic, ep_num)?;
+        let title_ = ep.title.clone().unwrap_or(page_title);
         log::info!("Saving episode {}: {}", ep_num, title_);
 
-        let recs = eps
-            .iter()
-            .enumerate()
-            .map(|(idx, img)| ComicRecord {
-                provider: super::Provider::Naver,
-                comic_id: comic_id_.to_owned(),
-                episode_seq: ep_num as i32,
-                episode_name: Some(title_.clone()),
-                image_seq: idx as i32 + 1,
-                image: img.to_owned(),
-                updated_at: chrono::Local::now().naive_local(),
-            })
-            .collect::<Vec<_>>();
-
-        diesel::insert_into(comics)
-            .values(&recs)
-            .execute(conn)
-            .unwrap_or_else(|e| {
-                log::error!("Cannot insert images into database: {}", e);
-                0
-            });
-
-        diesel::insert_into(episodes)
-            .values(&EpisodeRecord {
-                provider: super::Provider::Naver,
-                comic_id: comic_id_.to_owned(),
-                episode_seq: ep_num as i32,
-                title: Some(title_.clone()),
-                images_cnt: recs.len() as i32,
-                created_at: chrono::Local::now().naive_local(),
-                last_update: chrono::Local::now().naive_local(),
-            })
-            .execute(conn)
-            .unwrap_or_else(|e| {
-                log::error!("Cannot insert images into database: {}", e);
-                0
-            });
+        // Thumbnails are nice to have, and never fail an episode
+        let thumbnail = ep.thumbnail_url.as_ref().and_then(|url| {
+            client
+                .get_bytes(client.get(url).set("User-Agent", FAKE_CHROME_74_UA))
+                .map_err(|e| log::warn!("Cannot fetch thumbnail of episode {}: {:#}", ep_num, e))
+                .ok()
+        });
+
+        super::save_episode(
+            conn,
+            super::Provider::NAVER,
+            comic_id_,
+            &super::Episode {
+                seq: ep_num as i32,
+                id: &ep_id,
+                name: &ep_id,
+                title: &title_,
+                published_on: ep.published_on,
+                thumbnail: thumbnail.as_deref(),
+                descrambled: false,
+            },
+            &eps,
+        )?;
     }
 
-    Ok(())
+    Ok(publication)
+}
+
+pub(crate) fn fetch_titles(client: &Client, comic_ids: Vec<String>) -> Result<Vec<String>> {
+    comic_ids
+        .iter()
+        .map(|comic_id| {
+            log::debug!("Fetching title for comic ID {}", comic_id);
+            let comic = ComicId::parse(comic_id)?;
+            match fetch_article_info_json(client, comic).and_then(|json| parse_article_info(&json))
+            {
+                Ok(info) => {
+                    check_league(comic, info.league())?;
+                    Ok(info.title_name)
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Cannot fetch title of {} from article API: {:#}",
+                        comic_id,
+                        e
+                    );
+                    Ok(
+                        fetch_episode_list_page(client, comic, 1, SortOrder::Ascending)?
+                            .comic_title,
+                    )
+                }
+            }
+        })
+        .collect::<Result<Vec<_>>>()
+}
+
+/// Runs probe stages on the first available episode, up to its first image. Pages read only
+/// when the JSON API fails are probed as well. Returns `None` once a stage fails.
+fn probe(client: &Client, comic_id: &str, report: &mut super::ProbeReport) -> Option<()> {
+    let comic = ComicId::parse(comic_id).ok()?;
+    let json = report.request(
+        "article list info API",
+        || fetch_article_info_json(client, comic),
+        |json| format!("{} bytes", json.len()),
+    )?;
+    report.parse(
+        "article list info (titleName, finished, webtoonLevelCode)",
+        || {
+            let info = parse_article_info(&json)?;
+            check_league(comic, info.league())?;
+            Ok(info)
+        },
+        |info| format!("{}, finished: {}", info.title_name, info.finished),
+    )?;
+
+    let json = report.request(
+        "article list API",
+        || fetch_article_list_json(client, comic, 1),
+        |json| format!("{} bytes", json.len()),
+    )?;
+    let ep_num = report
+        .parse(
+            "article list (articleList, pageInfo)",
+            || {
+                let list = parse_article_list_page(&json)?;
+                let article = list
+                    .article_list
+                    .iter()
+                    .find(|article| !article.charge)
+                    .ok_or_else(|| anyhow!("No free article in the first page of article list"))?;
+                Ok((
+                    list.article_list.len(),
+                    list.page_info.total_pages,
+                    article.no,
+                ))
+            },
+            |(count, pages, no)| {
+                format!(
+                    "{} episodes on the first of {} pages, first free episode {}",
+                    count, pages, no
+                )
+            },
+        )?
+        .2;
+
+    // Mobile list pages are read when the article API fails
+    let html = report.request(
+        "mobile list page (fallback)",
+        || fetch_episode_list_html(client, comic, 1, SortOrder::Ascending),
+        |html| format!("{} bytes", html.len()),
+    )?;
+    report.parse(
+        "mobile list page (og:title, ul.section_episode_list li.item)",
+        || {
+            let page = parse_episode_list_page(&html)?;
+            if page.episodes.is_empty() {
+                return Err(anyhow!("No li.item element with a link in episode list"));
+            }
+            Ok(page)
+        },
+        |page| {
+            format!(
+                "{}, {:?}, {} episodes on the first page",
+                page.comic_title,
+                page.publication,
+                page.episodes.len()
+            )
+        },
+    )?;
+
+    let json = report.request(
+        "article detail API",
+        || fetch_article_detail_json(client, comic, ep_num),
+        |json| format!("episode {}, {} bytes", ep_num, json.len()),
+    )?;
+    let detail = report.parse(
+        "article detail (subtitle, imageList)",
+        || {
+            let detail = parse_article_detail(&json)?;
+            if detail.image_list.is_empty() {
+                return Err(anyhow!("No image in imageList"));
+            }
+            Ok(detail)
+        },
+        |detail| format!("{}, {} images", detail.subtitle, detail.image_list.len()),
+    )?;
+
+    // Episode pages are read when the article detail API fails
+    let html = report.request(
+        "episode page (fallback)",
+        || fetch_episode_html(client, comic, ep_num),
+        |html| format!("episode {}, {} bytes", ep_num, html.len()),
+    )?;
+    report.parse(
+        "episode page (.tit_area h3, .wt_viewer img)",
+        || {
+            let (title, image_links) = parse_episode_page(&html)?;
+            if image_links.is_empty() {
+                return Err(anyhow!("No img element in .wt_viewer element"));
+            }
+            Ok((title, image_links))
+        },
+        |(title, image_links)| format!("{}, {} images", title, image_links.len()),
+    )?;
+
+    report.request(
+        "first image",
+        || {
+            client.get_bytes(
+                client
+                    .get(&detail.image_list[0].url)
+                    .set("User-Agent", FAKE_CHROME_74_UA),
+            )
+        },
+        |image| format!("{} bytes", image.len()),
+    )?;
+
+    Some(())
 }
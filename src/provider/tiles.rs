@@ -0,0 +1,69 @@
+//! Restores Lezhin images whose tiles are shuffled.
+//!
+//! A shuffled image is split into a 5x5 grid of equal tiles, which are permuted by a
+//! Fisher-Yates shuffle driven by an xorshift generator seeded with the episode ID. Pixels past
+//! the last whole tile, on the right and bottom edges, are left in place.
+use crate::error::Result;
+use image::{GenericImage, GenericImageView, ImageFormat, ImageOutputFormat};
+
+/// Number of tiles on each side of an image
+const GRID: u32 = 5;
+/// Quality of re-encoded JPEG images, high enough not to lose visible detail
+const JPEG_QUALITY: u8 = 95;
+
+/// xorshift generator of the web viewer, which decides the order of tiles.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    /// Returns a number in `0..bound`.
+    fn next(&mut self, bound: u64) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        (x >> 32) % bound
+    }
+}
+
+/// Returns the order of tiles, such that tile `i` of the readable image is tile `order[i]` of
+/// the shuffled one. Tiles are numbered row by row.
+fn tile_order(seed: u64) -> Vec<u32> {
+    let mut random = Random { state: seed };
+    let mut order = (0..GRID * GRID).collect::<Vec<_>>();
+    for i in (1..order.len()).rev() {
+        let j = random.next(i as u64 + 1) as usize;
+        order.swap(i, j);
+    }
+    order
+}
+
+/// Reorders tiles of a shuffled image of the episode with ID `seed`. The image is encoded again
+/// in its own format.
+pub(crate) fn descramble(data: &[u8], seed: u64) -> Result<Vec<u8>> {
+    let format = image::guess_format(data)?;
+    let shuffled = image::load_from_memory_with_format(data, format)?;
+    let tile_width = shuffled.width() / GRID;
+    let tile_height = shuffled.height() / GRID;
+
+    let mut restored = shuffled.clone();
+    for (to, from) in (0..).zip(tile_order(seed)) {
+        let tile = shuffled.view(
+            from % GRID * tile_width,
+            from / GRID * tile_height,
+            tile_width,
+            tile_height,
+        );
+        restored.copy_from(&tile, to % GRID * tile_width, to / GRID * tile_height)?;
+    }
+
+    let output = match format {
+        ImageFormat::Png => ImageOutputFormat::Png,
+        _ => ImageOutputFormat::Jpeg(JPEG_QUALITY),
+    };
+    let mut encoded = Vec::with_capacity(data.len());
+    restored.write_to(&mut encoded, output)?;
+    Ok(encoded)
+}
@@ -0,0 +1,342 @@
+//! Stand-in server for provider sites, to run providers without network access.
+//!
+//! In record mode the server forwards each request to the real site and stores the exchange in
+//! a fixture directory. In replay mode it answers requests from those fixtures only.
+//! Clients reach the server through [`ClientOptions::stand_in`](crate::http::ClientOptions),
+//! which puts the original host as the first path segment: a request to
+//! `https://m.comic.naver.com/webtoon/list.nhn` becomes
+//! `http://<stand-in address>/m.comic.naver.com/webtoon/list.nhn`.
+//!
+//! Each exchange is stored as `NNNN.json` with the response status and headers, and
+//! `NNNN.body` with the response body. Request bodies and headers are never stored, as they
+//! would contain passwords and cookies; response headers are stored as is, so recorded
+//! fixtures may contain session cookies issued by the site.
+use crate::error::Result;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// Headers which describe the connection or the encoding of the stored body, rather than the
+/// response itself.
+const HOP_HEADERS: &[&str] = &[
+    "connection",
+    "content-encoding",
+    "content-length",
+    "keep-alive",
+    "transfer-encoding",
+];
+
+/// Metadata of a recorded exchange. Its body is stored in a separate file.
+#[derive(Serialize, Deserialize, Debug)]
+struct Exchange {
+    method: String,
+    /// URL of the real site
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+/// A running stand-in server. It stops when dropped.
+pub struct StandIn {
+    server: Arc<tiny_http::Server>,
+    thread: Option<JoinHandle<()>>,
+    base_url: String,
+}
+
+impl StandIn {
+    /// Starts a server on `addr` which forwards requests to real sites, recording exchanges to
+    /// `dir`.
+    pub fn record(dir: impl Into<PathBuf>, addr: &str) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let mut recorder = Recorder {
+            dir,
+            agent: ureq::AgentBuilder::new().redirects(0).build(),
+            count: 0,
+        };
+        Self::start(addr, move |req, url| recorder.handle(req, url))
+    }
+
+    /// Starts a server on `addr` which answers requests with exchanges recorded in `dir`.
+    /// Exchanges of the same request are replayed in recorded order, and the last one repeats.
+    pub fn replay(dir: impl AsRef<Path>, addr: &str) -> Result<Self> {
+        let mut replayer = Replayer::load(dir.as_ref())?;
+        Self::start(addr, move |req, url| replayer.handle(req, url))
+    }
+
+    fn start(
+        addr: &str,
+        mut handle: impl FnMut(&mut tiny_http::Request, &str) -> Result<Response> + Send + 'static,
+    ) -> Result<Self> {
+        let server = Arc::new(tiny_http::Server::http(addr).map_err(|e| anyhow!(e))?);
+        let base_url = format!("http://{}", server.server_addr());
+
+        let thread = {
+            let server = Arc::clone(&server);
+            let base_url = base_url.clone();
+            std::thread::spawn(move || {
+                for mut req in server.incoming_requests() {
+                    let resp = upstream_url(&req)
+                        .and_then(|url| {
+                            let resp = handle(&mut req, &url)?;
+                            Ok(resp.into_tiny_http(&url, &base_url))
+                        })
+                        .unwrap_or_else(|e| {
+                            log::error!("Stand-in cannot answer {}: {:#}", req.url(), e);
+                            tiny_http::Response::from_data(format!("{:#}", e).into_bytes())
+                                .with_status_code(502)
+                        });
+                    let url = req.url().to_owned();
+                    if let Err(e) = req.respond(resp) {
+                        log::warn!("Cannot respond to {}: {}", url, e);
+                    }
+                }
+            })
+        };
+
+        log::info!("Stand-in server listening on {}", base_url);
+        Ok(Self {
+            server,
+            thread: Some(thread),
+            base_url,
+        })
+    }
+
+    /// Base URL to be set as [`ClientOptions::stand_in`](crate::http::ClientOptions).
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Blocks until the server stops, which is never unless it fails.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for StandIn {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Response of the real site, before it is adapted to the stand-in server.
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Converts into a response of the stand-in server, so that redirections and cookies of the
+    /// real site keep working through it. `url` is the URL of the real site requested.
+    fn into_tiny_http(
+        self,
+        url: &str,
+        base_url: &str,
+    ) -> tiny_http::Response<std::io::Cursor<Vec<u8>>> {
+        let mut resp = tiny_http::Response::from_data(self.body).with_status_code(self.status);
+        for (name, value) in &self.headers {
+            let value = match name.to_ascii_lowercase().as_str() {
+                name if HOP_HEADERS.contains(&name) => continue,
+                "location" => stand_in_location(url, value, base_url),
+                "set-cookie" => stand_in_cookie(value),
+                _ => value.to_owned(),
+            };
+            match tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+                Ok(header) => resp.add_header(header),
+                Err(()) => log::warn!("Dropping malformed header {}", name),
+            }
+        }
+        resp
+    }
+}
+
+/// Returns the URL of the real site a stand-in request is for.
+fn upstream_url(req: &tiny_http::Request) -> Result<String> {
+    let path = req.url().trim_start_matches('/');
+    if path.split('/').next().unwrap_or_default().is_empty() {
+        return Err(anyhow!(
+            "Expected host of the real site as the first path segment of {}",
+            req.url()
+        ));
+    }
+    Ok(format!("https://{}", path))
+}
+
+/// Identifies a request regardless of the order of its query parameters.
+fn request_key(method: &str, url: &str) -> Result<String> {
+    let mut url = url::Url::parse(url)?;
+    let mut query = url.query_pairs().into_owned().collect::<Vec<_>>();
+    query.sort();
+    url.set_query(None);
+    if !query.is_empty() {
+        url.query_pairs_mut().extend_pairs(query);
+    }
+    Ok(format!("{} {}", method, url))
+}
+
+/// Points a redirection from `url` of the real site at the stand-in server.
+fn stand_in_location(url: &str, location: &str, base_url: &str) -> String {
+    let location = match url::Url::parse(url).and_then(|url| url.join(location)) {
+        Ok(location) => location,
+        Err(_) => return location.to_owned(),
+    };
+    format!(
+        "{}/{}",
+        base_url,
+        &location[url::Position::BeforeHost..url::Position::AfterQuery]
+    )
+}
+
+/// Makes a cookie of the real site acceptable for the stand-in server, which is a plain HTTP
+/// server on another host.
+fn stand_in_cookie(cookie: &str) -> String {
+    let mut parts = cookie.split(';');
+    let mut adapted = parts.next().unwrap_or_default().to_owned();
+    for attr in parts {
+        let name = attr.split('=').next().unwrap_or_default().trim();
+        // Paths of the real site are under its host on the stand-in server
+        if !["domain", "path", "samesite", "secure"]
+            .iter()
+            .any(|dropped| name.eq_ignore_ascii_case(dropped))
+        {
+            adapted.push(';');
+            adapted += attr;
+        }
+    }
+    adapted + "; Path=/"
+}
+
+struct Recorder {
+    dir: PathBuf,
+    agent: ureq::Agent,
+    count: usize,
+}
+
+impl Recorder {
+    fn handle(&mut self, req: &mut tiny_http::Request, url: &str) -> Result<Response> {
+        let method = req.method().as_str().to_owned();
+
+        let mut upstream = self.agent.request(&method, url);
+        for header in req.headers() {
+            let name = header.field.as_str().as_str();
+            if !name.eq_ignore_ascii_case("host")
+                && !name.eq_ignore_ascii_case("accept-encoding")
+                && !HOP_HEADERS.contains(&name.to_ascii_lowercase().as_str())
+            {
+                upstream = upstream.set(name, header.value.as_str());
+            }
+        }
+        let mut body = Vec::new();
+        req.as_reader().read_to_end(&mut body)?;
+
+        let resp = match upstream.send_bytes(&body) {
+            Ok(resp) | Err(ureq::Error::Status(_, resp)) => resp,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut names = resp.headers_names();
+        names.dedup();
+        let headers = names
+            .iter()
+            .flat_map(|name| {
+                resp.all(name)
+                    .into_iter()
+                    .map(move |value| (name.clone(), value.to_owned()))
+            })
+            .collect::<Vec<_>>();
+        let status = resp.status();
+        let mut body = Vec::new();
+        resp.into_reader().read_to_end(&mut body)?;
+
+        self.count += 1;
+        let stem = self.dir.join(format!("{:04}", self.count));
+        std::fs::write(
+            stem.with_extension("json"),
+            serde_json::to_string_pretty(&Exchange {
+                method,
+                url: url.to_owned(),
+                status,
+                headers: headers.clone(),
+            })?,
+        )?;
+        std::fs::write(stem.with_extension("body"), &body)?;
+        log::info!("Recorded {} {} as {:?}", status, url, stem);
+
+        Ok(Response {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+struct Replayer {
+    /// Recorded exchanges by request, with their body files
+    exchanges: HashMap<String, VecDeque<(Exchange, PathBuf)>>,
+}
+
+impl Replayer {
+    fn load(dir: &Path) -> Result<Self> {
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+        paths.sort();
+
+        let mut exchanges: HashMap<_, VecDeque<_>> = HashMap::new();
+        for path in paths {
+            let exchange: Exchange = serde_json::from_str(&std::fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("Malformed fixture {:?}: {}", path, e))?;
+            exchanges
+                .entry(request_key(&exchange.method, &exchange.url)?)
+                .or_default()
+                .push_back((exchange, path.with_extension("body")));
+        }
+        log::debug!(
+            "Loaded fixtures of {} requests from {:?}",
+            exchanges.len(),
+            dir
+        );
+
+        Ok(Self { exchanges })
+    }
+
+    fn handle(&mut self, req: &mut tiny_http::Request, url: &str) -> Result<Response> {
+        let key = request_key(req.method().as_str(), url)?;
+        let recorded = self
+            .exchanges
+            .get_mut(&key)
+            .ok_or_else(|| anyhow!("No fixture for {}", key))?;
+        let (exchange, body) = if recorded.len() > 1 {
+            recorded.pop_front().expect("checked length")
+        } else {
+            let (exchange, body) = &recorded[0];
+            (
+                Exchange {
+                    method: exchange.method.clone(),
+                    url: exchange.url.clone(),
+                    status: exchange.status,
+                    headers: exchange.headers.clone(),
+                },
+                body.clone(),
+            )
+        };
+        log::debug!("Replaying {} for {}", exchange.status, key);
+
+        Ok(Response {
+            status: exchange.status,
+            headers: exchange.headers,
+            body: std::fs::read(body)?,
+        })
+    }
+}
@@ -7,6 +7,21 @@ table! {
         image_seq -> Integer,
         image -> Binary,
         updated_at -> Timestamp,
+        descrambled -> Bool,
+    }
+}
+
+table! {
+    comics_history (provider, comic_id, episode_seq, image_seq, archived_at) {
+        provider -> Text,
+        comic_id -> Text,
+        episode_seq -> Integer,
+        episode_name -> Nullable<Text>,
+        image_seq -> Integer,
+        image -> Binary,
+        updated_at -> Timestamp,
+        archived_at -> Timestamp,
+        descrambled -> Bool,
     }
 }
 
@@ -19,6 +34,30 @@ table! {
         images_count -> Integer,
         created_at -> Timestamp,
         last_update -> Timestamp,
+        episode_id -> Nullable<Text>,
+        episode_name -> Nullable<Text>,
+        published_on -> Nullable<Date>,
+        thumbnail -> Nullable<Binary>,
+    }
+}
+
+table! {
+    episode_gaps (provider, comic_id, episode_id) {
+        provider -> Text,
+        comic_id -> Text,
+        episode_id -> Text,
+        found_at -> Timestamp,
+        kind -> Integer,
+    }
+}
+
+table! {
+    pending_episodes (provider, comic_id, episode_id) {
+        provider -> Text,
+        comic_id -> Text,
+        episode_id -> Text,
+        title -> Nullable<Text>,
+        freed_at -> Timestamp,
     }
 }
 
@@ -28,6 +67,19 @@ table! {
         id -> Text,
         status -> Integer,
         last_scraping -> Timestamp,
+        last_error -> Nullable<Text>,
+        failures -> Integer,
+        scraping_interval -> Nullable<Integer>,
+    }
+}
+
+table! {
+    sessions (provider, account) {
+        provider -> Text,
+        account -> Text,
+        cookies -> Text,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
     }
 }
 
@@ -39,4 +91,13 @@ table! {
     }
 }
 
-allow_tables_to_appear_in_same_query!(comics, episodes, scraping_targets, titles,);
+allow_tables_to_appear_in_same_query!(
+    comics,
+    comics_history,
+    episode_gaps,
+    episodes,
+    pending_episodes,
+    scraping_targets,
+    sessions,
+    titles,
+);
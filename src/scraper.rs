@@ -1,69 +1,609 @@
-use crate::error::Result;
-use crate::provider::Provider;
+use crate::credential::{Credential, Credentials};
+use crate::error::{Error, Result};
+use crate::http::{Client, ClientOptions};
+use crate::models::{GapKind, ScrapingStatus, ScrapingTarget};
+use crate::provider::{ProbeReport, Provider, Publication};
+use crate::session;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 pub(crate) const FAKE_UA: &str =
     "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:79.0) Gecko/20100101 Firefox/79.0";
 
+/// Delay before retrying a failed target in watch mode. Doubled on each consecutive failure.
+const BASE_FAILURE_DELAY: Duration = Duration::from_secs(60);
+/// Upper bound of the delay before retrying a failed target.
+const MAX_FAILURE_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+/// Longest sleep of watch mode, so that changes of targets are noticed in time.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Builds a client logged in to the provider of each of given comics which has a credential.
+/// Providers with imported browser cookies use them as is. A stored session is reused until
+/// it expires or the site rejects it; otherwise the client logs in with password and the new
+/// session is stored.
+///
+/// A failed login does not stop logging in to other providers. It is returned by provider
+/// along with the client, so that only targets of that provider fail.
+fn authenticated_client<'a>(
+    conn: &SqliteConnection,
+    builder: ureq::AgentBuilder,
+    options: ClientOptions,
+    comics: impl IntoIterator<Item = (Provider, &'a str)>,
+    credentials: &Credentials,
+) -> Result<(Client, HashMap<Provider, anyhow::Error>)> {
+    let mut store = browser_cookies(credentials)?;
+
+    let mut comic_ids: HashMap<Provider, Vec<&str>> = HashMap::new();
+    for (provider_, comic_id) in comics {
+        comic_ids.entry(provider_).or_default().push(comic_id);
+    }
+
+    let mut logins = Vec::new();
+    for provider_ in Provider::registry()
+        .iter()
+        .filter(|p| comic_ids.contains_key(p))
+    {
+        let base_url = url::Url::parse(&options.url(provider_.base_url()))?;
+        if !store.matches(&base_url).is_empty() {
+            log::debug!("Using browser cookies for provider {}", provider_);
+            continue;
+        }
+
+        let cred = match credentials.get(*provider_) {
+            Some(cred) => cred,
+            None => {
+                log::info!(
+                    "No credential for provider {}; continuing without authentication",
+                    provider_
+                );
+                continue;
+            }
+        };
+
+        match session::find(conn, *provider_, &cred.id)? {
+            Some(cookies) => {
+                log::debug!("Reusing stored session for provider {}", provider_);
+                for line in cookies.lines() {
+                    let cookie: cookie_store::Cookie = serde_json::from_str(line)?;
+                    // Sessions stored by older versions may hold short-lived cookies
+                    if cookie.is_expired() {
+                        log::debug!(
+                            "Ignoring expired cookie {} of stored session",
+                            cookie.name()
+                        );
+                        continue;
+                    }
+                    store.insert(cookie, &base_url)?;
+                }
+            }
+            None => logins.push((*provider_, cred)),
+        }
+    }
+
+    let client = Client::new(builder.cookie_store(store).build(), options);
+
+    let mut login_failures = HashMap::new();
+    for (provider_, cred) in logins {
+        if let Err(e) = log_in(conn, &client, provider_, cred, &comic_ids[&provider_]) {
+            log::error!("Logging in to provider {} failed: {:#}", provider_, e);
+            login_failures.insert(provider_, e);
+        }
+    }
+
+    Ok((client, login_failures))
+}
+
+/// Logs in to the provider with password, and stores the new session.
+fn log_in(
+    conn: &SqliteConnection,
+    client: &Client,
+    provider_: Provider,
+    cred: &Credential,
+    comic_ids: &[&str],
+) -> Result<()> {
+    let before_login = session::snapshot(client, provider_)?;
+    provider_.authenticate(client, &cred.id, &cred.password, comic_ids)?;
+    log::debug!("Client authentication succeeded for provider {}", provider_);
+    session::save(conn, client, provider_, &cred.id, &before_login)
+}
+
+/// Whether the site rejected the session, so that logging in again may help.
+fn is_session_rejected(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(cause.downcast_ref::<Error>(), Some(Error::AuthFailure))
+            || matches!(
+                cause.downcast_ref::<ureq::Error>(),
+                Some(ureq::Error::Status(401, _)) | Some(ureq::Error::Status(403, _))
+            )
+    })
+}
+
+/// Returns a cookie store holding imported browser cookies.
+fn browser_cookies(credentials: &Credentials) -> Result<cookie_store::CookieStore> {
+    let mut store = cookie_store::CookieStore::default();
+    for (cookie, url) in credentials.cookies() {
+        store.insert(cookie.clone(), url)?;
+    }
+    Ok(store)
+}
+
+/// Runs each stage of scraping a comic, without any database. Browser cookies or the
+/// credential of the provider are used as in scraping, but the login session is not stored.
+pub fn probe(
+    provider_: Provider,
+    comic_id: &str,
+    credentials: &Credentials,
+    options: ClientOptions,
+) -> Result<ProbeReport> {
+    provider_.validate_comic_id(comic_id)?;
+    let store = browser_cookies(credentials)?;
+    let base_url = url::Url::parse(&options.url(provider_.base_url()))?;
+    let has_cookies = !store.matches(&base_url).is_empty();
+    let client = Client::new(
+        ureq::AgentBuilder::new()
+            .user_agent(FAKE_UA)
+            .redirects(0)
+            .cookie_store(store)
+            .build(),
+        options,
+    );
+
+    let mut report = ProbeReport::default();
+    if let Some(cred) = credentials.get(provider_).filter(|_| !has_cookies) {
+        report.request(
+            "login",
+            || provider_.authenticate(&client, &cred.id, &cred.password, &[comic_id]),
+            |()| format!("logged in as {}", cred.id),
+        );
+        if report.failure().is_some() {
+            return Ok(report);
+        }
+    }
+    provider_.probe(&client, comic_id, &mut report);
+
+    Ok(report)
+}
+
+/// Outcome of a scraping run.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Targets scraped successfully, as `provider/id`
+    pub succeeded: Vec<String>,
+    /// Targets failed to be scraped, as `provider/id`, with the cause
+    pub failed: Vec<(String, anyhow::Error)>,
+    /// Episodes which are not free yet, as `provider/id` of the target, episode title and
+    /// the time it becomes available
+    pub pending: Vec<(String, String, NaiveDateTime)>,
+}
+
+/// Scrapes a single target and records the outcome in `scraping_targets`.
+/// A scraping failure is returned after it is recorded.
+///
+/// A finished comic is marked as complete once none of its episodes is pending or unavailable,
+/// such as paid ones. Deleted episodes do not keep it from being complete.
+///
+/// If the site rejects the session, e.g. a stored one revoked by the site, it is dropped and
+/// the target is tried once more after logging in with password.
+fn scrape_target(
+    conn: &SqliteConnection,
+    client: &Client,
+    target: &ScrapingTarget,
+    credentials: &Credentials,
+) -> Result<()> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    log::info!("Scraping target {}/{}", target.provider, target.id);
+
+    let row = scraping_targets.find((target.provider, &target.id));
+    let fetched = match target.provider.fetch_episodes(client, &target.id, conn) {
+        Err(e) if is_session_rejected(&e) => match credentials.get(target.provider) {
+            Some(cred) => {
+                log::warn!(
+                    "Provider {} rejected the session: {:#}; logging in again",
+                    target.provider,
+                    e
+                );
+                session::delete(conn, target.provider, &cred.id)?;
+                log_in(conn, client, target.provider, cred, &[&target.id])
+                    .and_then(|()| target.provider.fetch_episodes(client, &target.id, conn))
+            }
+            None => Err(e),
+        },
+        fetched => fetched,
+    };
+    match fetched {
+        Ok(publication) => {
+            // Freed episodes are saved by now, unless they were taken down
+            forget_freed_episodes(conn, target)?;
+            diesel::update(row)
+                .set((
+                    last_scraping.eq(chrono::Local::now().naive_local()),
+                    last_error.eq(None::<String>),
+                    failures.eq(0),
+                ))
+                .execute(conn)?;
+
+            if publication == Publication::Finished
+                && !has_pending_episodes(conn, target)?
+                && !has_unavailable_episodes(conn, target)?
+            {
+                log::info!(
+                    "Target {}/{} is finished and fully scraped; marking as complete",
+                    target.provider,
+                    target.id
+                );
+                diesel::update(row)
+                    .set(status.eq(ScrapingStatus::Complete))
+                    .execute(conn)?;
+            }
+            Ok(())
+        }
+        Err(e) => fail_target(conn, target, e),
+    }
+}
+
+/// Records a failure of the target in `scraping_targets`, and returns it.
+fn fail_target(conn: &SqliteConnection, target: &ScrapingTarget, e: anyhow::Error) -> Result<()> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    diesel::update(scraping_targets.find((target.provider, &target.id)))
+        .set((last_error.eq(format!("{:#}", e)), failures.eq(failures + 1)))
+        .execute(conn)?;
+    Err(e)
+}
+
+/// Fails the target because logging in to its provider failed with `cause`.
+fn fail_login(
+    conn: &SqliteConnection,
+    target: &ScrapingTarget,
+    cause: &anyhow::Error,
+) -> Result<()> {
+    let e = anyhow::anyhow!("Cannot log in to provider {}: {:#}", target.provider, cause);
+    fail_target(conn, target, e)
+}
+
+/// Removes pending episodes of the target which should have been freed already.
+fn forget_freed_episodes(conn: &SqliteConnection, target: &ScrapingTarget) -> Result<()> {
+    use crate::schema::pending_episodes::dsl::*;
+
+    diesel::delete(
+        pending_episodes
+            .filter(provider.eq(target.provider))
+            .filter(comic_id.eq(&target.id))
+            .filter(freed_at.le(chrono::Local::now().naive_local())),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Returns whether the target has episodes which are not free yet.
+fn has_pending_episodes(conn: &SqliteConnection, target: &ScrapingTarget) -> Result<bool> {
+    use crate::schema::pending_episodes::dsl::*;
+    use diesel::dsl::{exists, select};
+
+    Ok(select(exists(
+        pending_episodes
+            .filter(provider.eq(target.provider))
+            .filter(comic_id.eq(&target.id)),
+    ))
+    .get_result(conn)?)
+}
+
+/// Returns whether the target has listed episodes which cannot be downloaded.
+fn has_unavailable_episodes(conn: &SqliteConnection, target: &ScrapingTarget) -> Result<bool> {
+    use crate::schema::episode_gaps::dsl::*;
+    use diesel::dsl::{exists, select};
+
+    Ok(select(exists(
+        episode_gaps
+            .filter(provider.eq(target.provider))
+            .filter(comic_id.eq(&target.id))
+            .filter(kind.eq(GapKind::Unavailable)),
+    ))
+    .get_result(conn)?)
+}
+
+/// Returns targets which have pending episodes freed by now.
+fn targets_with_freed_episodes(conn: &SqliteConnection) -> Result<HashSet<(Provider, String)>> {
+    use crate::schema::pending_episodes::dsl::*;
+
+    Ok(pending_episodes
+        .filter(freed_at.le(chrono::Local::now().naive_local()))
+        .select((provider, comic_id))
+        .load::<(Provider, String)>(conn)?
+        .into_iter()
+        .collect())
+}
+
+/// Returns episodes which are not free yet, in the order they become available.
+fn pending_episodes(conn: &SqliteConnection) -> Result<Vec<(String, String, NaiveDateTime)>> {
+    use crate::models::PendingEpisode;
+    use crate::schema::pending_episodes::dsl::*;
+
+    Ok(pending_episodes
+        .filter(freed_at.gt(chrono::Local::now().naive_local()))
+        .order_by(freed_at)
+        .load::<PendingEpisode>(conn)?
+        .into_iter()
+        .map(|ep| {
+            (
+                format!("{}/{}", ep.provider, ep.comic_id),
+                ep.title.unwrap_or(ep.episode_id),
+                ep.freed_at,
+            )
+        })
+        .collect())
+}
+
 /// Starts scraping.
 /// Target lists are in given database's `scrap_targets` table.
-pub fn start(conn: &SqliteConnection, id_: &str, pw_: &str) -> Result<()> {
-    use crate::models::{ScrapingStatus, ScrapingTarget};
+/// A failure of a target does not stop scraping other targets.
+///
+/// If `freed_only` is set, only targets with pending episodes freed since the last run are
+/// scraped.
+pub fn start(
+    conn: &SqliteConnection,
+    credentials: &Credentials,
+    options: ClientOptions,
+    freed_only: bool,
+) -> Result<Summary> {
     use crate::schema::scraping_targets::dsl::*;
-    let targets: Vec<crate::models::ScrapingTarget> =
-        scraping_targets.load::<ScrapingTarget>(conn)?;
-    let agent = ureq::AgentBuilder::new()
-        .user_agent(FAKE_UA)
-        .redirects(0)
-        .build();
-
-    Provider::Lezhin.authenticate(&agent, id_, pw_)?; // TODO: Authenticate client for other providers
-    log::debug!(
-        "Client authentication succeeded for provider {}",
-        Provider::Lezhin
-    );
+    let freed = targets_with_freed_episodes(conn)?;
+    let targets = scraping_targets
+        .load::<ScrapingTarget>(conn)?
+        .into_iter()
+        .filter(|target| {
+            if target.status != ScrapingStatus::Enabled {
+                log::debug!(
+                    "Ignoring target {}/{} due to its status: {:?}",
+                    target.provider,
+                    target.id,
+                    target.status
+                );
+                false
+            } else if freed_only && !freed.contains(&(target.provider, target.id.clone())) {
+                log::debug!(
+                    "Ignoring target {}/{} without freed episodes",
+                    target.provider,
+                    target.id
+                );
+                false
+            } else {
+                true
+            }
+        })
+        .collect::<Vec<_>>();
+    let (client, login_failures) = authenticated_client(
+        conn,
+        ureq::AgentBuilder::new().user_agent(FAKE_UA).redirects(0),
+        options,
+        targets.iter().map(|t| (t.provider, t.id.as_str())),
+        credentials,
+    )?;
 
+    let mut summary = Summary::default();
     for target in targets {
-        if target.status != ScrapingStatus::Enabled {
-            log::debug!(
-                "Ignoring target {}/{} due to its status: {:?}",
-                target.provider,
-                target.id,
-                target.status
-            );
-            continue;
+        let label = format!("{}/{}", target.provider, target.id);
+        let scraped = match login_failures.get(&target.provider) {
+            Some(cause) => fail_login(conn, &target, cause),
+            None => scrape_target(conn, &client, &target, credentials),
+        };
+        match scraped {
+            Ok(()) => summary.succeeded.push(label),
+            Err(e) => {
+                log::error!("Scraping target {} failed: {:#}", label, e);
+                summary.failed.push((label, e));
+            }
         }
+    }
 
-        log::info!("Scraping target {}/{}", target.provider, target.id);
+    summary.pending = pending_episodes(conn)?;
+    Ok(summary)
+}
 
-        target.provider.fetch_episodes(&agent, &target.id, conn)?;
-        diesel::update(scraping_targets.find((target.provider, target.id)))
-            .set(last_scraping.eq(chrono::Local::now().naive_local()))
-            .execute(conn)?;
+/// Returns when the target should be scraped next in watch mode.
+/// `failed_at` is the time of the last failure of the target in this run, if any.
+fn next_scraping(
+    target: &ScrapingTarget,
+    default_interval: Duration,
+    failed_at: Option<NaiveDateTime>,
+) -> NaiveDateTime {
+    let delay = if target.failures > 0 {
+        BASE_FAILURE_DELAY
+            .checked_mul(1 << (target.failures - 1).min(16))
+            .unwrap_or(MAX_FAILURE_DELAY)
+            .min(MAX_FAILURE_DELAY)
+    } else {
+        target.scraping_interval.map_or(default_interval, |secs| {
+            Duration::from_secs(secs.max(0) as u64)
+        })
+    };
+
+    failed_at.unwrap_or(target.last_scraping)
+        + chrono::Duration::from_std(delay).unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+/// Sleeps until `deadline` or until `stop` is set, whichever comes first.
+fn sleep_until(deadline: NaiveDateTime, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        let remaining = match (deadline - chrono::Local::now().naive_local()).to_std() {
+            Ok(remaining) if remaining > Duration::from_secs(0) => remaining,
+            _ => return,
+        };
+        std::thread::sleep(remaining.min(Duration::from_secs(1)));
+    }
+}
+
+/// Scrapes targets on schedule until `stop` is set.
+///
+/// An enabled target is scraped once its interval has passed since `last_scraping`, or as soon
+/// as one of its pending episodes is freed. A failed target is retried with exponential
+/// backoff. The client is authenticated once and kept while providers of due targets stay the
+/// same. Targets of a provider whose login failed fail alike, and the login is tried again
+/// once they are due.
+pub fn watch(
+    conn: &SqliteConnection,
+    credentials: &Credentials,
+    options: ClientOptions,
+    default_interval: Duration,
+    stop: &AtomicBool,
+) -> Result<()> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    // Client with the providers it is logged in to
+    let mut client: Option<(Client, HashSet<Provider>)> = None;
+    let mut login_failures: HashMap<Provider, anyhow::Error> = HashMap::new();
+    let mut auth_failures = 0;
+    // Targets failed in this run, with the time of their last failure
+    let mut failed_at: HashMap<(Provider, String), NaiveDateTime> = HashMap::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let now = chrono::Local::now().naive_local();
+        let freed = targets_with_freed_episodes(conn)?;
+        let targets = scraping_targets
+            .filter(status.eq(ScrapingStatus::Enabled))
+            .load::<ScrapingTarget>(conn)?;
+
+        let mut next_wakeup = now
+            + chrono::Duration::from_std(WATCH_POLL_INTERVAL)
+                .expect("poll interval fits chrono::Duration");
+        let mut due = Vec::new();
+        for target in &targets {
+            let key = (target.provider, target.id.clone());
+            let next = next_scraping(target, default_interval, failed_at.get(&key).copied());
+            if next <= now || freed.contains(&key) {
+                due.push(target);
+            } else {
+                next_wakeup = next_wakeup.min(next);
+            }
+        }
+
+        if !due.is_empty() {
+            let mut providers = due.iter().map(|t| t.provider).collect::<HashSet<_>>();
+            let authenticated = client
+                .as_ref()
+                .is_some_and(|(_, authed)| providers.is_subset(authed));
+            if !authenticated {
+                if let Some((_, authed)) = &client {
+                    providers.extend(authed);
+                }
+                match authenticated_client(
+                    conn,
+                    ureq::AgentBuilder::new().user_agent(FAKE_UA).redirects(0),
+                    options.clone(),
+                    targets
+                        .iter()
+                        .filter(|t| providers.contains(&t.provider))
+                        .map(|t| (t.provider, t.id.as_str())),
+                    credentials,
+                ) {
+                    Ok((new_client, failures_)) => {
+                        providers.retain(|p| !failures_.contains_key(p));
+                        client = Some((new_client, providers));
+                        login_failures = failures_;
+                        auth_failures = 0;
+                    }
+                    Err(e) => {
+                        let delay = BASE_FAILURE_DELAY
+                            .checked_mul(1 << auth_failures.min(10))
+                            .unwrap_or(MAX_FAILURE_DELAY)
+                            .min(MAX_FAILURE_DELAY);
+                        auth_failures += 1;
+                        log::error!("Authentication failed: {:#}; retrying in {:?}", e, delay);
+                        sleep_until(
+                            now + chrono::Duration::from_std(delay)
+                                .expect("failure delay fits chrono::Duration"),
+                            stop,
+                        );
+                        continue;
+                    }
+                }
+            }
+        }
+
+        for target in due {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let (client, _) = client
+                .as_ref()
+                .expect("client is authenticated for due targets");
+            let key = (target.provider, target.id.clone());
+            let scraped = match login_failures.get(&target.provider) {
+                Some(cause) => fail_login(conn, target, cause),
+                None => scrape_target(conn, client, target, credentials),
+            };
+            match scraped {
+                Ok(()) => {
+                    failed_at.remove(&key);
+                }
+                Err(e) => {
+                    log::error!(
+                        "Scraping target {}/{} failed: {:#}",
+                        target.provider,
+                        target.id,
+                        e
+                    );
+                    failed_at.insert(key, chrono::Local::now().naive_local());
+                }
+            }
+        }
+
+        log::debug!("Next scraping at {}", next_wakeup);
+        sleep_until(next_wakeup, stop);
     }
 
+    log::info!("Watch stopped");
     Ok(())
 }
 
-/// Scrape unknown titles.
-pub fn scrap_titles(conn: &SqliteConnection, id_: &str, pw_: &str) -> Result<usize> {
+/// Scrape unknown titles of every provider.
+pub fn scrap_titles(
+    conn: &SqliteConnection,
+    credentials: &Credentials,
+    options: ClientOptions,
+) -> Result<usize> {
     use crate::schema::titles::dsl::*;
 
-    let targets = titles
-        .select(id)
-        .filter(provider.eq(Provider::Lezhin))
-        .filter(title.is_null())
-        .load(conn)?;
+    let mut updated = 0;
 
-    let agent = ureq::Agent::new();
-    Provider::Lezhin.authenticate(&agent, id_, pw_)?;
+    for provider_ in Provider::registry() {
+        let targets = titles
+            .select(id)
+            .filter(provider.eq(provider_))
+            .filter(title.is_null())
+            .load::<String>(conn)?;
+
+        if targets.is_empty() {
+            log::debug!("No unknown titles for provider {}", provider_);
+            continue;
+        }
+
+        let (client, mut login_failures) = authenticated_client(
+            conn,
+            ureq::AgentBuilder::new(),
+            options.clone(),
+            targets.iter().map(|t| (*provider_, t.as_str())),
+            credentials,
+        )?;
+        // The failure is logged already; titles of other providers are still fetched
+        if login_failures.remove(provider_).is_some() {
+            log::warn!("Skipping unknown titles of provider {}", provider_);
+            continue;
+        }
+
+        let titles_ = provider_.fetch_titles(&client, targets.clone())?;
+        for (target, title_) in targets.iter().zip(titles_.iter()) {
+            diesel::update(titles.find((provider_, target)))
+                .set(title.eq(title_))
+                .execute(conn)?;
+        }
 
-    let titles_ = Provider::Lezhin.fetch_titles(&agent, targets.clone())?;
-    for (target, title_) in targets.iter().zip(titles_.iter()) {
-        diesel::update(titles.find((Provider::Lezhin, target)))
-            .set(title.eq(title_))
-            .execute(conn)?;
+        updated += targets.len();
     }
 
-    Ok(targets.len())
+    Ok(updated)
 }
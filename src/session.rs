@@ -0,0 +1,120 @@
+use crate::error::Result;
+use crate::http::Client;
+use crate::provider::Provider;
+use chrono::{DateTime, Duration, NaiveDateTime};
+use diesel::prelude::*;
+use std::collections::HashSet;
+
+/// Lifetime of a stored session which consists of session cookies only.
+const SESSION_MAX_AGE_DAYS: i64 = 7;
+
+/// Returns cookies of the stored session of given account, unless it has expired.
+pub(crate) fn find(
+    conn: &SqliteConnection,
+    provider_: Provider,
+    account_: &str,
+) -> Result<Option<String>> {
+    use crate::schema::sessions::dsl::*;
+
+    Ok(sessions
+        .find((provider_, account_))
+        .filter(expires_at.gt(chrono::Utc::now().naive_utc()))
+        .select(cookies)
+        .first(conn)
+        .optional()?)
+}
+
+/// Forgets the stored session of given account, e.g. after the site rejected it.
+pub(crate) fn delete(conn: &SqliteConnection, provider_: Provider, account_: &str) -> Result<()> {
+    use crate::schema::sessions::dsl::*;
+
+    diesel::delete(sessions.find((provider_, account_))).execute(conn)?;
+    Ok(())
+}
+
+/// Returns cookies of `client` for the provider, serialized as they are stored.
+pub(crate) fn snapshot(client: &Client, provider_: Provider) -> Result<HashSet<String>> {
+    let url = url::Url::parse(&client.url(provider_.base_url()))?;
+    let store = client.agent().cookie_store();
+    store
+        .matches(&url)
+        .into_iter()
+        .map(|cookie| Ok(serde_json::to_value(cookie)?.to_string()))
+        .collect()
+}
+
+/// Stores cookies of `client` for the provider, replacing the previous session of given account.
+///
+/// The session expires with the first of the provider's [auth
+/// cookies](crate::provider::ComicProvider::auth_cookies) which expires, so that short-lived
+/// cookies of other purposes do not end it early. If the provider names none, cookies set by
+/// the login decide it, which are those not in `before_login`, a [`snapshot`] taken before
+/// logging in. Cookies which expire before the session are not stored.
+pub(crate) fn save(
+    conn: &SqliteConnection,
+    client: &Client,
+    provider_: Provider,
+    account_: &str,
+    before_login: &HashSet<String>,
+) -> Result<()> {
+    use crate::models::SessionRecord;
+    use crate::schema::sessions::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    let url = url::Url::parse(&client.url(provider_.base_url()))?;
+    let store = client.agent().cookie_store();
+    let jar = store.matches(&url);
+    let auth_cookies = provider_.auth_cookies();
+
+    let mut saved = Vec::new();
+    let mut expires = None;
+    for cookie in jar {
+        let value = serde_json::to_value(cookie)?;
+        let line = value.to_string();
+        let is_auth_cookie = if auth_cookies.is_empty() {
+            !before_login.contains(&line)
+        } else {
+            auth_cookies.contains(&cookie.name())
+        };
+
+        // cookie_store does not export its expiration type, so read it from serialized form.
+        // Session cookies are serialized as "SessionEnd" and have no expiration time.
+        let expires_at_ = match value["expires"]["AtUtc"].as_str() {
+            Some(at) => Some(DateTime::parse_from_rfc3339(at)?.naive_utc()),
+            None => None,
+        };
+        if let Some(at) = expires_at_.filter(|_| is_auth_cookie) {
+            expires = Some(expires.map_or(at, |e: NaiveDateTime| e.min(at)));
+        }
+        saved.push((line, expires_at_));
+    }
+    // The session lasts until its first auth cookie expires
+    let expires = expires.unwrap_or_else(|| now + Duration::days(SESSION_MAX_AGE_DAYS));
+
+    // Cookies which expire before the session would be stale when it is restored
+    let mut serialized = String::new();
+    for (line, expires_at_) in saved {
+        if !matches!(expires_at_, Some(at) if at < expires) {
+            serialized += &line;
+            serialized.push('\n');
+        }
+    }
+
+    diesel::replace_into(sessions)
+        .values(&SessionRecord {
+            provider: provider_,
+            account: account_.to_owned(),
+            cookies: serialized,
+            created_at: now,
+            expires_at: expires,
+        })
+        .execute(conn)?;
+
+    log::debug!(
+        "Stored session of provider {} which expires at {}",
+        provider_,
+        expires
+    );
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use crate::error::Result;
+use crate::models::{ScrapingStatus, ScrapingTarget};
+use crate::provider::Provider;
+use anyhow::anyhow;
+use diesel::prelude::*;
+
+/// Adds an enabled target, which is scraped by the next `scrape` or `watch` run.
+pub fn add(conn: &SqliteConnection, provider_: Provider, id_: &str) -> Result<()> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    provider_.validate_comic_id(id_)?;
+
+    if scraping_targets
+        .find((provider_, id_))
+        .first::<ScrapingTarget>(conn)
+        .optional()?
+        .is_some()
+    {
+        return Err(anyhow!("Target {}/{} already exists", provider_, id_));
+    }
+
+    diesel::insert_into(scraping_targets)
+        .values(&ScrapingTarget {
+            provider: provider_,
+            id: id_.to_owned(),
+            status: ScrapingStatus::Enabled,
+            // Never scraped, so that `watch` picks it up right away
+            last_scraping: chrono::NaiveDateTime::from_timestamp(0, 0),
+            last_error: None,
+            failures: 0,
+            scraping_interval: None,
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Removes a target. Episodes scraped already are kept.
+pub fn remove(conn: &SqliteConnection, provider_: Provider, id_: &str) -> Result<()> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    match diesel::delete(scraping_targets.find((provider_, id_))).execute(conn)? {
+        0 => Err(anyhow!("No target {}/{}", provider_, id_)),
+        _ => Ok(()),
+    }
+}
+
+/// Changes status of a target.
+pub fn set_status(
+    conn: &SqliteConnection,
+    provider_: Provider,
+    id_: &str,
+    status_: ScrapingStatus,
+) -> Result<()> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    match diesel::update(scraping_targets.find((provider_, id_)))
+        .set(status.eq(status_))
+        .execute(conn)?
+    {
+        0 => Err(anyhow!("No target {}/{}", provider_, id_)),
+        _ => Ok(()),
+    }
+}
+
+/// Sets seconds between scrapings of a target in watch mode, or restores the default interval
+/// of `watch` if `None`.
+pub fn set_interval(
+    conn: &SqliteConnection,
+    provider_: Provider,
+    id_: &str,
+    interval: Option<i32>,
+) -> Result<()> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    if interval.is_some_and(|secs| secs <= 0) {
+        return Err(anyhow!("Scraping interval must be positive"));
+    }
+
+    match diesel::update(scraping_targets.find((provider_, id_)))
+        .set(scraping_interval.eq(interval))
+        .execute(conn)?
+    {
+        0 => Err(anyhow!("No target {}/{}", provider_, id_)),
+        _ => Ok(()),
+    }
+}
+
+/// Returns every target, ordered by provider and ID.
+pub fn list(conn: &SqliteConnection) -> Result<Vec<ScrapingTarget>> {
+    use crate::schema::scraping_targets::dsl::*;
+
+    Ok(scraping_targets
+        .order_by((provider, id))
+        .load::<ScrapingTarget>(conn)?)
+}
@@ -1,11 +1,26 @@
 use crate::error::Result;
 use diesel::prelude::*;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use std::io::{Cursor, Empty};
 use std::str::FromStr;
 use tiny_http::{Header, Response, StatusCode};
 
 type BytesResponse = Response<Cursor<Vec<u8>>>;
 
+/// Characters escaped in comic IDs of links. Comic IDs may contain `/`, e.g. `en/<alias>`.
+const COMIC_ID: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+fn encode_comic_id(comic_id: &str) -> String {
+    utf8_percent_encode(comic_id, COMIC_ID).to_string()
+}
+
+fn decode_comic_id(path: &str) -> Option<String> {
+    percent_decode_str(path)
+        .decode_utf8()
+        .ok()
+        .map(|comic_id| comic_id.into_owned())
+}
+
 fn redirect_root() -> Response<Empty> {
     Response::empty(StatusCode(301))
         .with_header(Header::from_str("Location: /list-comics").unwrap())
@@ -27,7 +42,7 @@ fn comic_pics(
         .filter(comic_id.eq(comic_id_))
         .filter(episode_seq.eq(episode_id))
         .order_by(image_seq)
-        .load::<ComicRecord>(&*conn)?;
+        .load::<ComicRecord>(conn)?;
 
     fn into_embedded_image(rec: &ComicRecord) -> String {
         format!(
@@ -56,10 +71,9 @@ Found {} records, response size {}MiB, title {}<br />
 </div>
 </html>"#,
         recs.len(),
-        f64::from(resp.bytes().len() as u32) / (1024f64 * 1024f64),
+        f64::from(resp.len() as u32) / (1024f64 * 1024f64),
         recs.iter()
-            .map(|x| x.episode_name.clone())
-            .flatten()
+            .filter_map(|x| x.episode_name.clone())
             .next()
             .unwrap_or_else(|| String::from("(unknown)")),
         resp,
@@ -72,12 +86,12 @@ fn list_comics(conn: &SqliteConnection) -> Result<BytesResponse> {
     use crate::models::TitleRecord;
     use crate::schema::titles::dsl::*;
 
-    let tvec = titles.order_by(title).load::<TitleRecord>(&*conn)?;
+    let tvec = titles.order_by(title).load::<TitleRecord>(conn)?;
 
     fn into_list_row(rec: TitleRecord) -> String {
         format!(
             r#"<a href="/list-episodes/{}">{} ({})</a><br>"#,
-            rec.id,
+            encode_comic_id(&rec.id),
             rec.title.unwrap_or_else(|| String::from("title unknown")),
             rec.id,
         )
@@ -106,7 +120,7 @@ fn list_episodes(target_id: String, conn: &SqliteConnection) -> Result<BytesResp
     fn into_list_row((_comic, _episode, _episode_seq): (String, Option<String>, i32)) -> String {
         format!(
             r#"<a href="/comic/{}/{}">{}</a><br>"#,
-            _comic,
+            encode_comic_id(&_comic),
             _episode_seq,
             _episode.unwrap_or_else(|| String::from("title unknown")),
         )
@@ -117,7 +131,7 @@ fn list_episodes(target_id: String, conn: &SqliteConnection) -> Result<BytesResp
         .distinct()
         .filter(id.eq(target_id))
         .order_by(seq)
-        .load(&*conn)?
+        .load(conn)?
         .into_iter()
         .map(into_list_row)
         .collect::<Vec<String>>()
@@ -166,22 +180,19 @@ pub fn serve(addr: impl std::net::ToSocketAddrs, conn: SqliteConnection) {
                 respond!(request, static_css());
             }
             url => {
-                if let Some(episode_path) = url.strip_prefix("/list-episodes/") {
-                    respond!(
-                        request,
-                        list_episodes(String::from(episode_path), &conn).unwrap()
-                    )
-                } else if let Some(path) = url.strip_prefix("/comic/") {
-                    let splits = path.split('/').collect::<Vec<_>>();
-                    respond!(
-                        request,
-                        comic_pics(
-                            splits[0].to_string(),
-                            splits[1].parse::<i32>().unwrap(),
-                            &conn
-                        )
-                        .unwrap()
-                    )
+                if let Some(comic_id) = url
+                    .strip_prefix("/list-episodes/")
+                    .and_then(decode_comic_id)
+                {
+                    respond!(request, list_episodes(comic_id, &conn).unwrap())
+                } else if let Some((comic_id, episode_seq)) = url
+                    .strip_prefix("/comic/")
+                    .and_then(|path| path.rsplit_once('/'))
+                    .and_then(|(comic_id, episode_seq)| {
+                        Some((decode_comic_id(comic_id)?, episode_seq.parse::<i32>().ok()?))
+                    })
+                {
+                    respond!(request, comic_pics(comic_id, episode_seq, &conn).unwrap())
                 } else {
                     respond!(request, Response::from_string("Unknown request"));
                 }
@@ -0,0 +1,780 @@
+//! Scrapes providers end-to-end against recorded fixtures served by a stand-in server.
+#[macro_use]
+extern crate diesel_migrations;
+
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use lzn::credential::Credentials;
+use lzn::http::ClientOptions;
+use lzn::models::{GapKind, ScrapingStatus};
+use lzn::provider::Provider;
+use lzn::replay::StandIn;
+
+embed_migrations!();
+
+fn setup() -> SqliteConnection {
+    let conn = SqliteConnection::establish(":memory:").unwrap();
+    embedded_migrations::run(&conn).unwrap();
+    conn
+}
+
+fn scrape(conn: &SqliteConnection, fixtures: &str) -> lzn::scraper::Summary {
+    scrape_as(conn, fixtures, &Credentials::default())
+}
+
+fn scrape_as(
+    conn: &SqliteConnection,
+    fixtures: &str,
+    credentials: &Credentials,
+) -> lzn::scraper::Summary {
+    let stand_in = StandIn::replay(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/").to_owned() + fixtures,
+        "127.0.0.1:0",
+    )
+    .unwrap();
+    let options = ClientOptions {
+        stand_in: Some(stand_in.base_url().to_owned()),
+        ..Default::default()
+    };
+    lzn::scraper::start(conn, credentials, options, false).unwrap()
+}
+
+/// Scrapes finished Naver comic with titleId 12345, whose episode 3 is deleted and episode 5 is
+/// a paid preview.
+fn scrape_naver(fixtures: &str, id: &str) -> SqliteConnection {
+    use lzn::schema::comics::dsl::*;
+
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, id).unwrap();
+
+    let summary = scrape(&conn, fixtures);
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    assert_eq!(summary.succeeded, vec![format!("Naver/{}", id)]);
+
+    let images = comics
+        .select((episode_seq, image_seq, image))
+        .order((episode_seq, image_seq))
+        .load::<(i32, i32, Vec<u8>)>(&conn)
+        .unwrap();
+    assert_eq!(
+        images,
+        vec![
+            (1, 1, b"episode 1 image 1\n".to_vec()),
+            (1, 2, b"episode 1 image 2\n".to_vec()),
+            (2, 1, b"episode 2 image 1\n".to_vec()),
+            (2, 2, b"episode 2 image 2\n".to_vec()),
+            (4, 1, b"episode 4 image 1\n".to_vec()),
+            (4, 2, b"episode 4 image 2\n".to_vec()),
+        ]
+    );
+
+    assert_eq!(
+        gaps(&conn),
+        vec![
+            ("3".to_owned(), GapKind::Missing),
+            ("5".to_owned(), GapKind::Unavailable)
+        ]
+    );
+
+    // The comic is finished, but its paid episode is not scraped yet
+    let targets = lzn::target::list(&conn).unwrap();
+    assert_eq!(targets[0].status, ScrapingStatus::Enabled);
+
+    conn
+}
+
+fn gaps(conn: &SqliteConnection) -> Vec<(String, GapKind)> {
+    use lzn::schema::episode_gaps::dsl::*;
+
+    episode_gaps
+        .select((episode_id, kind))
+        .order(episode_id)
+        .load(conn)
+        .unwrap()
+}
+
+/// A finished comic is complete without its deleted episodes.
+#[test]
+fn naver_deleted_episodes() {
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, "12345").unwrap();
+
+    let summary = scrape(&conn, "naver-deleted");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    assert_eq!(gaps(&conn), vec![("3".to_owned(), GapKind::Missing)]);
+
+    let targets = lzn::target::list(&conn).unwrap();
+    assert_eq!(targets[0].status, ScrapingStatus::Complete);
+}
+
+#[test]
+fn naver_article_api() {
+    use lzn::schema::episodes::dsl::*;
+
+    let conn = scrape_naver("naver", "12345");
+
+    let details = episodes
+        .select((seq, title, published_on, thumbnail))
+        .order(seq)
+        .load::<(i32, Option<String>, Option<NaiveDate>, Option<Vec<u8>>)>(&conn)
+        .unwrap();
+    let date = |y, m, d| Some(NaiveDate::from_ymd_opt(y, m, d).unwrap());
+    assert_eq!(
+        details,
+        vec![
+            (
+                1,
+                Some("1화. 에피소드 1".to_owned()),
+                date(2020, 1, 5),
+                Some(b"episode 1 thumbnail\n".to_vec())
+            ),
+            (
+                2,
+                Some("2화. 에피소드 2".to_owned()),
+                date(2020, 1, 12),
+                Some(b"episode 2 thumbnail\n".to_vec())
+            ),
+            (
+                4,
+                Some("4화. 에피소드 4".to_owned()),
+                date(2020, 1, 26),
+                Some(b"episode 4 thumbnail\n".to_vec())
+            ),
+        ]
+    );
+}
+
+/// A stored session revoked by the site is dropped, and the target is scraped after logging
+/// in again.
+#[test]
+fn naver_revoked_session() {
+    use lzn::schema::sessions::dsl::*;
+
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, "12345").unwrap();
+
+    let url = "http://127.0.0.1/".parse().unwrap();
+    let stale = cookie_store::Cookie::parse("NID_AUT=stale; Path=/", &url).unwrap();
+    let now = chrono::Utc::now().naive_utc();
+    diesel::insert_into(sessions)
+        .values((
+            provider.eq(Provider::NAVER),
+            account.eq("reader"),
+            cookies.eq(serde_json::to_string(&stale).unwrap() + "\n"),
+            created_at.eq(now),
+            expires_at.eq(now + chrono::Duration::days(1)),
+        ))
+        .execute(&conn)
+        .unwrap();
+
+    let credentials = r#"{"naver": {"id": "reader", "password": "secret"}}"#
+        .parse()
+        .unwrap();
+    let summary = scrape_as(&conn, "naver-session", &credentials);
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    assert_eq!(summary.succeeded, vec!["Naver/12345"]);
+
+    let (stored, expires) = sessions
+        .select((cookies, expires_at))
+        .first::<(String, chrono::NaiveDateTime)>(&conn)
+        .unwrap();
+    assert!(stored.contains("NID_AUT=fresh"), "{}", stored);
+    assert!(!stored.contains("NID_AUT=stale"), "{}", stored);
+    // Login cookies last for the browser session; the short-lived nid_inf does not count, and
+    // is not stored as it would expire before the session
+    assert!(expires > now + chrono::Duration::days(6), "{}", expires);
+    assert!(!stored.contains("nid_inf"), "{}", stored);
+}
+
+/// Logging in again after the site revoked the session fails on a wrong password, even though
+/// the revoked session cookie is still held.
+#[test]
+fn naver_relogin_failure() {
+    use lzn::schema::sessions::dsl::*;
+
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, "12345").unwrap();
+
+    let url = "http://127.0.0.1/".parse().unwrap();
+    let stale = cookie_store::Cookie::parse("NID_AUT=stale; Path=/", &url).unwrap();
+    let now = chrono::Utc::now().naive_utc();
+    diesel::insert_into(sessions)
+        .values((
+            provider.eq(Provider::NAVER),
+            account.eq("reader"),
+            cookies.eq(serde_json::to_string(&stale).unwrap() + "\n"),
+            created_at.eq(now),
+            expires_at.eq(now + chrono::Duration::days(1)),
+        ))
+        .execute(&conn)
+        .unwrap();
+
+    let credentials = r#"{"naver": {"id": "reader", "password": "wrong"}}"#
+        .parse()
+        .unwrap();
+    let summary = scrape_as(&conn, "naver-relogin-failure", &credentials);
+    assert!(summary.succeeded.is_empty(), "{:?}", summary.succeeded);
+    assert_eq!(summary.failed.len(), 1);
+    assert!(
+        format!("{:#}", summary.failed[0].1).contains("Authentication failure"),
+        "{:#}",
+        summary.failed[0].1
+    );
+
+    // The revoked session is not stored again
+    assert_eq!(sessions.count().get_result::<i64>(&conn).unwrap(), 0);
+}
+
+/// Expired cookies of a stored session are dropped, rather than failing to restore it.
+#[test]
+fn naver_session_with_expired_cookie() {
+    use lzn::schema::sessions::dsl::*;
+
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, "12345").unwrap();
+
+    let url = "http://127.0.0.1/".parse().unwrap();
+    let stored = [
+        "NID_AUT=valid; Path=/",
+        "nid_inf=tracking; Path=/; Max-Age=1",
+    ]
+    .iter()
+    .map(|cookie| {
+        let cookie = cookie_store::Cookie::parse(*cookie, &url).unwrap();
+        serde_json::to_string(&cookie).unwrap() + "\n"
+    })
+    .collect::<String>();
+    let now = chrono::Utc::now().naive_utc();
+    diesel::insert_into(sessions)
+        .values((
+            provider.eq(Provider::NAVER),
+            account.eq("reader"),
+            cookies.eq(stored),
+            created_at.eq(now),
+            expires_at.eq(now + chrono::Duration::days(1)),
+        ))
+        .execute(&conn)
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+
+    // The fixtures have no login; the stored session is used as is
+    let credentials = r#"{"naver": {"id": "reader", "password": "secret"}}"#
+        .parse()
+        .unwrap();
+    let summary = scrape_as(&conn, "naver", &credentials);
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    assert_eq!(summary.succeeded, vec!["Naver/12345"]);
+}
+
+/// A failed login fails targets of that provider only.
+#[test]
+fn login_failure_of_another_provider() {
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, "12345").unwrap();
+    lzn::target::add(&conn, Provider::LEZHIN, "replay").unwrap();
+
+    let credentials = r#"{"naver": {"id": "reader", "password": "wrong"}}"#
+        .parse()
+        .unwrap();
+    let summary = scrape_as(&conn, "login-failure", &credentials);
+    assert_eq!(summary.succeeded, vec!["Lezhin/replay"]);
+    assert_eq!(summary.failed.len(), 1);
+    assert_eq!(summary.failed[0].0, "Naver/12345");
+
+    let naver = lzn::target::list(&conn)
+        .unwrap()
+        .into_iter()
+        .find(|target| target.provider == Provider::NAVER)
+        .unwrap();
+    assert_eq!(naver.status, ScrapingStatus::Enabled);
+    assert_eq!(naver.failures, 1);
+    assert!(naver
+        .last_error
+        .unwrap()
+        .contains("Cannot log in to provider Naver"));
+}
+
+/// A failed login skips unknown titles of that provider only.
+#[test]
+fn titles_despite_login_failure() {
+    use lzn::schema::titles::dsl::*;
+
+    let conn = setup();
+    diesel::insert_into(titles)
+        .values(&vec![
+            (provider.eq(Provider::NAVER), id.eq("12345")),
+            (provider.eq(Provider::LEZHIN), id.eq("replay")),
+        ])
+        .execute(&conn)
+        .unwrap();
+
+    let stand_in = StandIn::replay(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/login-failure"),
+        "127.0.0.1:0",
+    )
+    .unwrap();
+    let options = ClientOptions {
+        stand_in: Some(stand_in.base_url().to_owned()),
+        ..Default::default()
+    };
+    let credentials = r#"{"naver": {"id": "reader", "password": "wrong"}}"#
+        .parse()
+        .unwrap();
+    let updated = lzn::scraper::scrap_titles(&conn, &credentials, options).unwrap();
+    assert_eq!(updated, 1);
+
+    let names = titles
+        .select((id, title))
+        .order(id)
+        .load::<(String, Option<String>)>(&conn)
+        .unwrap();
+    assert_eq!(
+        names,
+        vec![
+            ("12345".to_owned(), None),
+            ("replay".to_owned(), Some("리플레이 테스트".to_owned())),
+        ]
+    );
+}
+
+/// Targets can be managed with provider names as listed.
+#[test]
+fn target_by_displayed_provider() {
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, "12345").unwrap();
+
+    let listed = lzn::target::list(&conn).unwrap()[0].provider.to_string();
+    assert_eq!(listed, "Naver");
+    let provider = listed.parse::<Provider>().unwrap();
+    lzn::target::set_status(&conn, provider, "12345", ScrapingStatus::Disabled).unwrap();
+    lzn::target::remove(&conn, provider, "12345").unwrap();
+}
+
+#[test]
+fn naver_mobile_list_pages() {
+    use lzn::schema::episodes::dsl::*;
+
+    let conn = scrape_naver("naver-legacy", "12345");
+
+    let titles = episodes
+        .select(title)
+        .order(seq)
+        .load::<Option<String>>(&conn)
+        .unwrap();
+    assert_eq!(
+        titles,
+        vec![
+            Some("1화".to_owned()),
+            Some("2화".to_owned()),
+            Some("4화".to_owned())
+        ]
+    );
+}
+
+#[test]
+fn naver_challenge_league() {
+    let conn = scrape_naver("naver-challenge", "challenge/12345");
+
+    let gaps = {
+        use lzn::schema::episode_gaps::dsl::*;
+        episode_gaps
+            .filter(comic_id.eq("challenge/12345"))
+            .count()
+            .get_result::<i64>(&conn)
+            .unwrap()
+    };
+    assert_eq!(gaps, 2);
+}
+
+/// Article APIs find a series by titleId alone, so a comic ID of another league is rejected.
+#[test]
+fn naver_league_mismatch() {
+    let conn = setup();
+    lzn::target::add(&conn, Provider::NAVER, "challenge/12345").unwrap();
+    lzn::target::add(&conn, Provider::NAVER, "bestChallenge/12345").unwrap();
+
+    let mut summary = scrape(&conn, "naver-challenge");
+    assert_eq!(summary.succeeded, vec!["Naver/challenge/12345"]);
+    assert_eq!(summary.failed.len(), 1);
+    let (target, e) = summary.failed.remove(0);
+    assert_eq!(target, "Naver/bestChallenge/12345");
+    assert!(
+        e.to_string().contains("series of challenge league"),
+        "{:#}",
+        e
+    );
+}
+
+#[test]
+fn lezhin_finished_comic() {
+    use lzn::schema::comics::dsl::*;
+
+    let conn = setup();
+    lzn::target::add(&conn, Provider::LEZHIN, "replay").unwrap();
+
+    let summary = scrape(&conn, "lezhin");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    assert_eq!(summary.succeeded, vec!["Lezhin/replay"]);
+
+    let images = comics
+        .select((episode_seq, image_seq, image))
+        .order((episode_seq, image_seq))
+        .load::<(i32, i32, Vec<u8>)>(&conn)
+        .unwrap();
+    assert_eq!(
+        images,
+        vec![
+            (1, 1, b"episode 1 image 1\n".to_vec()),
+            (1, 2, b"episode 1 image 2\n".to_vec()),
+            (2, 1, b"episode 2 image 1\n".to_vec()),
+            (2, 2, b"episode 2 image 2\n".to_vec()),
+        ]
+    );
+
+    let targets = lzn::target::list(&conn).unwrap();
+    assert_eq!(targets[0].status, ScrapingStatus::Complete);
+}
+
+/// Episodes with unrecognized names are recorded as gaps, and keep the comic from completing.
+#[test]
+fn lezhin_unrecognized_episode() {
+    let conn = setup();
+    lzn::target::add(&conn, Provider::LEZHIN, "replay").unwrap();
+
+    let summary = scrape(&conn, "lezhin-unrecognized");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    assert_eq!(summary.succeeded, vec!["Lezhin/replay"]);
+
+    assert_eq!(gaps(&conn), vec![("5004".to_owned(), GapKind::Unavailable)]);
+    let targets = lzn::target::list(&conn).unwrap();
+    assert_eq!(targets[0].status, ScrapingStatus::Enabled);
+}
+
+/// Runs migrations older than `version` only, to build a database of an earlier lzn.
+fn setup_before(version: &str) -> SqliteConnection {
+    let conn = SqliteConnection::establish(":memory:").unwrap();
+    let dir = std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/migrations"));
+    let migrations = diesel_migrations::migration_paths_in_directory(dir)
+        .unwrap()
+        .into_iter()
+        .map(|entry| diesel_migrations::migration_from(entry.path()).unwrap())
+        .filter(|migration| migration.version() < version)
+        .collect::<Vec<_>>();
+    diesel_migrations::run_migrations(&conn, migrations, &mut std::io::sink()).unwrap();
+    conn
+}
+
+/// Images of `comics_history` as (episode sequence, image).
+fn archived_images(conn: &SqliteConnection) -> Vec<(i32, Vec<u8>)> {
+    use lzn::schema::comics_history::dsl::*;
+
+    comics_history
+        .select((episode_seq, image))
+        .order(episode_seq)
+        .load(conn)
+        .unwrap()
+}
+
+/// Episodes stored before they had identifiers are adopted by their titles. Ones removed from
+/// Lezhin, and images whose episode was never stored, are archived out of the way.
+#[test]
+fn lezhin_legacy_episodes() {
+    use lzn::schema::comics::dsl::*;
+
+    let conn = setup_before("20261017120738");
+    diesel::sql_query(
+        "INSERT INTO comics VALUES
+            ('lezhin', 'replay', 0, '1화', 1, X'6c6567616379', '2021-01-01 00:00:00'),
+            ('lezhin', 'replay', 1, '삭제된 화', 1, X'72656d6f766564', '2021-01-01 00:00:00'),
+            ('lezhin', 'replay', 2, '2화', 1, X'6f727068616e', '2021-01-01 00:00:00')",
+    )
+    .execute(&conn)
+    .unwrap();
+    diesel::sql_query(
+        "INSERT INTO episodes VALUES
+            ('lezhin', 'replay', 0, '1화', 1, '2021-01-01 00:00:00', '2021-01-01 00:00:00'),
+            ('lezhin', 'replay', 1, '삭제된 화', 1, '2021-01-01 00:00:00', '2021-01-01 00:00:00')",
+    )
+    .execute(&conn)
+    .unwrap();
+    embedded_migrations::run(&conn).unwrap();
+    lzn::target::add(&conn, Provider::LEZHIN, "replay").unwrap();
+
+    let summary = scrape(&conn, "lezhin");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+
+    let images = comics
+        .select((episode_seq, image_seq, image))
+        .order((episode_seq, image_seq))
+        .load::<(i32, i32, Vec<u8>)>(&conn)
+        .unwrap();
+    assert_eq!(
+        images,
+        vec![
+            (1, 1, b"legacy".to_vec()),
+            (2, 1, b"episode 2 image 1\n".to_vec()),
+            (2, 2, b"episode 2 image 2\n".to_vec()),
+        ]
+    );
+    assert_eq!(
+        archived_images(&conn),
+        vec![(1, b"removed".to_vec()), (2, b"orphan".to_vec())]
+    );
+
+    // Nothing is left to scrape again
+    let summary = scrape(&conn, "lezhin");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    assert_eq!(archived_images(&conn).len(), 2);
+}
+
+/// An episode whose sequence number is held by another episode, e.g. after Lezhin renamed
+/// episodes, replaces it.
+#[test]
+fn lezhin_episode_taking_sequence() {
+    let conn = setup();
+    lzn::target::add(&conn, Provider::LEZHIN, "replay").unwrap();
+    diesel::sql_query(
+        "INSERT INTO comics (provider, comic_id, episode_seq, episode_name, image_seq, image)
+            VALUES ('lezhin', 'replay', 2, '옛 2화', 1, X'7265706c61636564')",
+    )
+    .execute(&conn)
+    .unwrap();
+    diesel::sql_query(
+        "INSERT INTO episodes (provider, id, seq, title, images_count, episode_id, episode_name)
+            VALUES ('lezhin', 'replay', 2, '옛 2화', 1, '4999', '2')",
+    )
+    .execute(&conn)
+    .unwrap();
+
+    let summary = scrape(&conn, "lezhin");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+
+    let stored = {
+        use lzn::schema::episodes::dsl::*;
+        episodes
+            .select((seq, episode_id))
+            .order(seq)
+            .load::<(i32, Option<String>)>(&conn)
+            .unwrap()
+    };
+    assert_eq!(
+        stored,
+        vec![(1, Some("5001".to_owned())), (2, Some("5002".to_owned()))]
+    );
+    assert_eq!(archived_images(&conn), vec![(2, b"replaced".to_vec())]);
+}
+
+#[test]
+fn lezhin_storefronts_are_separate_titles() {
+    use lzn::schema::sessions::dsl::{cookies, sessions};
+    use lzn::schema::titles::dsl::*;
+
+    let conn = setup();
+    lzn::target::add(&conn, Provider::LEZHIN, "replay").unwrap();
+    let (provider_, comic_id) =
+        Provider::from_comic_url("https://www.lezhin.com/en/comic/replay/1").unwrap();
+    assert_eq!(comic_id, "en/replay");
+    lzn::target::add(&conn, provider_, &comic_id).unwrap();
+
+    // Each storefront is logged in separately, and serves its episodes from its own inventory
+    let credentials = r#"{"lezhin": {"id": "reader", "password": "secret"}}"#
+        .parse()
+        .unwrap();
+    let mut summary = scrape_as(&conn, "lezhin-storefronts", &credentials);
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    summary.succeeded.sort();
+    assert_eq!(summary.succeeded, vec!["Lezhin/en/replay", "Lezhin/replay"]);
+
+    let stored = sessions.select(cookies).first::<String>(&conn).unwrap();
+    assert!(stored.contains("_lz_session_ko"), "{}", stored);
+    assert!(stored.contains("_lz_session_en"), "{}", stored);
+
+    let names = titles
+        .select((id, title))
+        .order(id)
+        .load::<(String, Option<String>)>(&conn)
+        .unwrap();
+    assert_eq!(
+        names,
+        vec![
+            ("en/replay".to_owned(), Some("Replay Test".to_owned())),
+            ("replay".to_owned(), Some("리플레이 테스트".to_owned())),
+        ]
+    );
+
+    let targets = lzn::target::list(&conn).unwrap();
+    assert!(targets
+        .iter()
+        .all(|target| target.status == ScrapingStatus::Complete));
+}
+
+#[test]
+fn lezhin_shuffled_tiles() {
+    use lzn::schema::comics::dsl::*;
+
+    let conn = setup();
+    lzn::target::add(&conn, Provider::LEZHIN, "replay").unwrap();
+
+    let summary = scrape(&conn, "lezhin-shuffled");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+
+    let images = comics
+        .select((episode_seq, image_seq, image, descrambled))
+        .order((episode_seq, image_seq))
+        .load::<(i32, i32, Vec<u8>, bool)>(&conn)
+        .unwrap();
+    assert_eq!(images.len(), 4);
+    for (episode, index, data, restored) in images {
+        assert!(restored);
+
+        // 53x52 image of 10x10 tiles colored by their position, with white edges
+        let decoded = ::image::load_from_memory(&data).unwrap().to_rgb8();
+        assert_eq!(decoded.dimensions(), (53, 52));
+        for (x, y, pixel) in decoded.enumerate_pixels() {
+            let expected = if x < 50 && y < 50 {
+                let tile = (y / 10 * 5 + x / 10) as u8;
+                [
+                    tile * 10,
+                    (episode * 50 + index * 20) as u8,
+                    255 - tile * 10,
+                ]
+            } else {
+                [255, 255, 255]
+            };
+            assert_eq!(
+                pixel.0, expected,
+                "episode {} image {} at ({}, {})",
+                episode, index, x, y
+            );
+        }
+    }
+}
+
+/// A 503 response with `Retry-After` is retried, unless retries are disabled.
+#[test]
+fn retry_service_unavailable() {
+    let get = |retries| {
+        let stand_in = StandIn::replay(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/retry"),
+            "127.0.0.1:0",
+        )
+        .unwrap();
+        let client = lzn::http::Client::new(
+            ureq::agent(),
+            ClientOptions {
+                retries,
+                stand_in: Some(stand_in.base_url().to_owned()),
+                ..Default::default()
+            },
+        );
+        let url = "https://comic.naver.com/api/article/list/info?titleId=12345";
+        client.get_string(client.get(url))
+    };
+
+    assert_eq!(get(1).unwrap(), "recovered\n");
+    assert!(get(0).is_err());
+}
+
+fn probe(fixtures: &str) -> lzn::provider::ProbeReport {
+    let stand_in = StandIn::replay(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/").to_owned() + fixtures,
+        "127.0.0.1:0",
+    )
+    .unwrap();
+    let options = ClientOptions {
+        stand_in: Some(stand_in.base_url().to_owned()),
+        ..Default::default()
+    };
+    lzn::scraper::probe(Provider::NAVER, "12345", &Credentials::default(), options).unwrap()
+}
+
+/// Pages read when the JSON API fails are probed as well.
+#[test]
+fn naver_probe_fallback_pages() {
+    let report = probe("naver-probe");
+    assert!(report.failure().is_none(), "{:?}", report.failure());
+    let stages = report
+        .stages
+        .iter()
+        .map(|stage| stage.name)
+        .collect::<Vec<_>>();
+    assert!(stages.contains(&"mobile list page (fallback)"));
+    assert!(stages.contains(&"episode page (fallback)"));
+    assert_eq!(stages.last(), Some(&"first image"));
+
+    let report = probe("naver-probe-redesign");
+    let failure = report.failure().unwrap();
+    assert_eq!(
+        failure.name,
+        "mobile list page (og:title, ul.section_episode_list li.item)"
+    );
+    assert_eq!(failure.kind, lzn::provider::StageKind::Parse);
+}
+
+/// Serves `conn` with `lzn serve` in the background, and returns its base URL.
+fn serve(conn: SqliteConnection) -> String {
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    // The server runs until the test process exits
+    std::thread::spawn(move || lzn::web::serve(addr, conn));
+    while std::net::TcpStream::connect(addr).is_err() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    format!("http://{}", addr)
+}
+
+fn get_page(base_url: &str, path: &str) -> String {
+    ureq::get(&(base_url.to_owned() + path))
+        .call()
+        .unwrap()
+        .into_string()
+        .unwrap()
+}
+
+/// Returns the first link in a page of `lzn serve`.
+fn first_link(page: &str) -> &str {
+    let start = page.find(r#"<a href=""#).unwrap() + r#"<a href=""#.len();
+    &page[start..start + page[start..].find('"').unwrap()]
+}
+
+/// Episode pages of comics whose IDs contain `/` are served.
+#[test]
+fn serve_naver_challenge_episode() {
+    let conn = scrape_naver("naver-challenge", "challenge/12345");
+    let base_url = serve(conn);
+    let get = |path: &str| get_page(&base_url, path);
+
+    let comics = get("/list-comics");
+    let episodes = get(first_link(&comics));
+    let link = first_link(&episodes);
+    assert_eq!(link, "/comic/challenge%2F12345/1");
+    let episode = get(link);
+    assert!(episode.contains("Found 2 records"), "{}", episode);
+
+    // Malformed episode numbers do not take down the server
+    assert_eq!(get("/comic/challenge/12345/first"), "Unknown request");
+    assert!(get(link).contains("Found 2 records"));
+}
+
+#[test]
+fn serve_lezhin_storefront_episode() {
+    let conn = setup();
+    lzn::target::add(&conn, Provider::LEZHIN, "en/replay").unwrap();
+    let summary = scrape(&conn, "lezhin-en");
+    assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+    let base_url = serve(conn);
+    let get = |path: &str| get_page(&base_url, path);
+
+    let episodes = get("/list-episodes/en%2Freplay");
+    let link = first_link(&episodes);
+    assert_eq!(link, "/comic/en%2Freplay/1");
+    let episode = get(link);
+    assert!(episode.contains("title Episode 1"), "{}", episode);
+
+    // "Next" links are relative to the episode page
+    assert!(episode.contains(r#"href="2">Next"#));
+    let next = get("/comic/en%2Freplay/2");
+    assert!(next.contains("title Episode 2"), "{}", next);
+}